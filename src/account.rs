@@ -0,0 +1,238 @@
+use crate::bapi::Credential;
+use crate::dirs::{config_dir, write_private};
+use crate::download::DownOptions;
+use crate::vault::Vault;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// 保存账号的文件名，在配置目录中
+pub const ACCOUNTS_NAME: &str = "accounts.json";
+/// SESSDATA在这么多秒内过期时提醒重新登录
+pub const EXPIRY_WARNING: i64 = 7 * 24 * 3600;
+
+/// 一个命名的账号：登录凭据和这个账号的默认下载设置
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    /// 加密保存在`Vault`中，解锁后由`Accounts::unlock`填入。
+    /// 旧版本明文保存在这个文件中，读入后迁移到`Vault`，迁移前保存时原样写回
+    #[serde(default, skip_serializing)]
+    pub credential: Credential,
+    /// 添加账号时从nav接口取得，只用来显示
+    #[serde(default)]
+    pub mid: i64,
+    #[serde(default)]
+    pub uname: String,
+    /// 默认的收藏夹、保存目录和下载选项，`parts`和`force`不保存
+    #[serde(default)]
+    pub defaults: Option<DownOptions>,
+}
+
+impl Profile {
+    pub fn new(name: &str, credential: Credential) -> Profile {
+        Profile {
+            name: name.to_string(),
+            credential,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_defaults(&mut self, opts: &DownOptions) {
+        self.defaults = Some(DownOptions {
+            force: false,
+            parts: Default::default(),
+            account: self.name.clone(),
+            ..opts.clone()
+        });
+    }
+}
+
+/// 保存的所有账号，按添加的顺序
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Accounts {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    profiles: Vec<Profile>,
+    /// 上次使用的账号，没有指定账号时用它
+    current: Option<String>,
+    /// 旧版本明文保存的凭据。写进凭据库之前保存时原样写回，不会丢失
+    #[serde(skip)]
+    plain: HashMap<String, Credential>,
+}
+
+impl Accounts {
+    pub fn load() -> Accounts {
+        Accounts::load_from(config_dir().map(|d| d.join(ACCOUNTS_NAME)))
+    }
+
+    fn load_from(path: Option<PathBuf>) -> Accounts {
+        let mut accounts: Accounts = path
+            .as_ref()
+            .and_then(|p| std::fs::read(p).ok())
+            .and_then(|s| serde_json::from_slice(&s).ok())
+            .unwrap_or_default();
+        accounts.path = path;
+        accounts.plain = accounts
+            .profiles
+            .iter()
+            .filter(|p| !p.credential.is_empty())
+            .map(|p| (p.name.clone(), p.credential.clone()))
+            .collect();
+        accounts
+    }
+
+    /// 文件中可能有旧版本的明文Cookie，在unix上只让自己读写
+    pub fn save(&self) -> std::io::Result<()> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "找不到配置目录"))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut value = serde_json::to_value(self)?;
+        if let Some(profiles) = value["profiles"].as_array_mut() {
+            for p in profiles.iter_mut() {
+                if let Some(c) = p["name"].as_str().and_then(|n| self.plain.get(n)) {
+                    p["credential"] = serde_json::to_value(c)?;
+                }
+            }
+        }
+        write_private(path, &serde_json::to_vec_pretty(&value)?)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.profiles.iter().map(|p| p.name.clone()).collect()
+    }
+
+    pub fn profiles(&self) -> &[Profile] {
+        &self.profiles
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Profile> {
+        self.profiles.iter_mut().find(|p| p.name == name)
+    }
+
+    /// 添加账号，已有同名的账号时更新凭据和账号信息，保留默认设置
+    pub fn upsert(&mut self, profile: Profile) {
+        match self.get_mut(&profile.name) {
+            Some(p) => {
+                p.credential = profile.credential;
+                p.mid = profile.mid;
+                p.uname = profile.uname;
+            }
+            None => self.profiles.push(profile),
+        }
+    }
+
+    /// 返回是否有这个账号
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.profiles.len();
+        self.profiles.retain(|p| p.name != name);
+        self.plain.remove(name);
+        if self.current.as_deref() == Some(name) {
+            self.current = None;
+        }
+        self.profiles.len() != len
+    }
+
+    pub fn current(&self) -> Option<&Profile> {
+        self.current.as_deref().and_then(|n| self.get(n))
+    }
+
+    pub fn set_current(&mut self, name: Option<&str>) {
+        self.current = name.map(String::from);
+    }
+
+    /// 从解锁的凭据库填入各账号的凭据。旧版本明文保存的凭据移进凭据库，
+    /// 这时返回true，调用者应该先保存凭据库，成功后调用`forget_plain`再保存账号
+    pub fn unlock(&mut self, vault: &mut Vault) -> bool {
+        let mut migrated = false;
+        for p in self.profiles.iter_mut() {
+            match vault.get(&p.name) {
+                Some(c) => p.credential = c.clone(),
+                None if !p.credential.is_empty() => {
+                    vault.insert(&p.name, p.credential.clone());
+                    migrated = true;
+                }
+                None => {}
+            }
+        }
+        migrated
+    }
+
+    /// 退出登录时去掉从凭据库填入的凭据，再用保存的账号要重新解锁。
+    /// 旧版本明文保存的凭据还在文件中，保留
+    pub fn lock(&mut self) {
+        for p in self.profiles.iter_mut() {
+            p.credential = self.plain.get(&p.name).cloned().unwrap_or_default();
+        }
+    }
+
+    /// 是否有旧版本明文保存的凭据，没有解锁时用来提醒迁移
+    pub fn has_plain_credentials(&self) -> bool {
+        !self.plain.is_empty()
+    }
+
+    /// 明文的凭据已经保存进凭据库，之后保存账号时不再写入
+    pub fn forget_plain(&mut self) {
+        self.plain.clear();
+    }
+}
+
+/// SESSDATA快过期或已过期时返回提醒，不知道过期时间时返回None
+pub fn expiry_warning(credential: &Credential) -> Option<String> {
+    let expires = credential.expires_at()?;
+    let left = expires - crate::manifest::now() as i64;
+    let date = crate::template::format_time(expires, "%Y-%m-%d %H:%M");
+    if left <= 0 {
+        Some(format!("Cookie已于{}过期，请重新登录", date))
+    } else if left < EXPIRY_WARNING {
+        Some(format!(
+            "Cookie将在{}天后（{}）过期，请及时重新登录",
+            left / 86400,
+            date
+        ))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_credentials_survive_until_forgotten() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("accounts.json");
+        std::fs::write(
+            &path,
+            r#"{"profiles":[{"name":"a","credential":{"sessdata":"s1"}},{"name":"b"}],"current":"a"}"#,
+        )
+        .unwrap();
+        let mut accounts = Accounts::load_from(Some(path.clone()));
+        assert!(accounts.has_plain_credentials());
+        // 没有解锁时保存，明文的凭据不能丢
+        accounts.set_current(Some("b"));
+        accounts.upsert(Profile::new("c", Credential::from_sessdata("s3")));
+        accounts.save().unwrap();
+        let reloaded = Accounts::load_from(Some(path.clone()));
+        assert_eq!(reloaded.get("a").unwrap().credential.sessdata, "s1");
+        assert!(reloaded.get("c").unwrap().credential.is_empty());
+        assert_eq!(reloaded.current().unwrap().name, "b");
+        accounts.lock();
+        assert_eq!(accounts.get("a").unwrap().credential.sessdata, "s1");
+        assert!(accounts.get("c").unwrap().credential.is_empty());
+
+        accounts.forget_plain();
+        accounts.save().unwrap();
+        let reloaded = Accounts::load_from(Some(path.clone()));
+        assert!(!reloaded.has_plain_credentials());
+    }
+}
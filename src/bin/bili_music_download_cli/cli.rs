@@ -0,0 +1,1144 @@
+use bili_music_download::account::{self, Accounts, Profile};
+use bili_music_download::bapi::{self, Credential};
+use bili_music_download::control::Control;
+use bili_music_download::cookies::{self, ChromiumKey};
+use bili_music_download::download::{start_download, DownOptions, FailKind, Summary};
+use bili_music_download::filter::{self, Expr};
+use bili_music_download::manifest::{Manifest, MirrorAction};
+use bili_music_download::sanitize::Strictness;
+use bili_music_download::selection::{Presets, Selection};
+use bili_music_download::template::{Template, DEFAULT_TEMPLATE};
+use bili_music_download::translit::{self, Script};
+use bili_music_download::vault::{self, Vault};
+use bili_music_download::watch::{self, shutdown_signal, WatchState};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+/// 部分视频下载失败
+const EXIT_FAILED: i32 = 1;
+/// 参数错误
+const EXIT_USAGE: i32 = 2;
+/// 网络或接口错误
+const EXIT_API: i32 = 3;
+/// 未登录或SESSDATA已失效
+const EXIT_NOT_LOGIN: i32 = 4;
+
+/// 凭据库的口令，不在终端中运行时用
+const PASSPHRASE_ENV: &str = "BILI_PASSPHRASE";
+
+/// 从Bilibili的视频收藏夹中下载音乐（命令行版）
+#[derive(StructOpt)]
+#[structopt(name = "bili_music_download_cli")]
+struct Cli {
+    /// SESSDATA，可以用`login`扫码获得
+    #[structopt(long, env = "BILI_SESSDATA", global = true, hide_env_values = true)]
+    sessdata: Option<String>,
+    /// 使用保存的账号及其默认设置，不指定时用`accounts use`选的账号，
+    /// 给了SESSDATA时只用SESSDATA。账号的Cookie加密保存，要输入口令或设置BILI_PASSPHRASE
+    #[structopt(long, env = "BILI_ACCOUNT", global = true)]
+    account: Option<String>,
+    /// 以JSON格式输出结果，下载过程的日志仍在stderr
+    #[structopt(long, global = true)]
+    json: bool,
+    #[structopt(subcommand)]
+    command: Commands,
+}
+
+#[derive(StructOpt)]
+enum Commands {
+    /// 扫码登录，输出SESSDATA
+    Login {
+        /// 保存为这个名字的账号并设为当前账号
+        #[structopt(long)]
+        save: Option<String>,
+        /// 不扫码，从cookies.txt或Firefox、Chromium的Cookie数据库导入
+        #[structopt(long, parse(from_os_str))]
+        cookies: Option<PathBuf>,
+        /// Chromium加密Cookie的密钥：64位十六进制的AES密钥（Windows），
+        /// 或密钥环、钥匙串中的密码（Linux、macOS）
+        #[structopt(long, env = "BILI_CHROMIUM_KEY", hide_env_values = true)]
+        chromium_key: Option<String>,
+        /// 和--cookies一起用：浏览器localStorage中的ac_time_value，
+        /// 有了它才能在Cookie需要刷新时自动刷新
+        #[structopt(
+            long,
+            requires = "cookies",
+            hide_env_values = true,
+            env = "BILI_REFRESH_TOKEN"
+        )]
+        refresh_token: Option<String>,
+    },
+    /// 把当前账号或--sessdata的Cookie导出为cookies.txt，给curl、yt-dlp等工具用
+    ExportCookies {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// 管理保存的账号
+    Accounts {
+        #[structopt(subcommand)]
+        command: AccountCommands,
+    },
+    /// 列出自己创建的收藏夹
+    Folders,
+    /// 列出收藏夹中的视频
+    List { fid: String },
+    /// 下载收藏夹或视频，可以是收藏夹编号、收藏夹链接、BV号或视频链接
+    Download {
+        #[structopt(required = true)]
+        targets: Vec<String>,
+        #[structopt(flatten)]
+        down: DownArgs,
+    },
+    /// 同步收藏夹：下载新视频，并处理已移出收藏夹的文件
+    Sync {
+        #[structopt(required = true)]
+        fids: Vec<String>,
+        #[structopt(flatten)]
+        down: DownArgs,
+        /// 已移出收藏夹的文件如何处理
+        #[structopt(long, default_value = "report", possible_values = &["report", "archive", "delete"])]
+        removed: Removed,
+        /// 只列出会处理哪些文件
+        #[structopt(long)]
+        dry_run: bool,
+        /// 只下载GUI中保存的选择预设选中的视频，每个收藏夹都要有同名的预设
+        #[structopt(long)]
+        preset: Option<String>,
+    },
+    /// 定时检查收藏夹，只下载新收藏的视频，Ctrl+C或SIGTERM退出
+    Watch {
+        #[structopt(required = true)]
+        fids: Vec<String>,
+        #[structopt(flatten)]
+        down: DownArgs,
+        /// 检查间隔（秒）
+        #[structopt(long, default_value = "3600")]
+        interval: u64,
+        /// 在间隔上随机增加的最长时间（秒）
+        #[structopt(long, default_value = "300")]
+        jitter: u64,
+    },
+}
+
+#[derive(StructOpt)]
+enum AccountCommands {
+    /// 列出保存的账号，*为当前账号
+    List,
+    /// 用--sessdata给的Cookie添加账号，已有时更新Cookie
+    Add {
+        name: String,
+    },
+    Remove {
+        name: String,
+    },
+    /// 设为当前账号
+    Use {
+        name: String,
+    },
+    /// 把下载选项保存为账号的默认设置，之后没有在命令行上给出的选项用它
+    Defaults {
+        name: String,
+        /// GUI中默认的收藏夹
+        #[structopt(long, default_value = "")]
+        fid: String,
+        #[structopt(flatten)]
+        down: DownArgs,
+    },
+}
+
+#[derive(StructOpt)]
+struct DownArgs {
+    /// 保存目录，默认为当前目录
+    #[structopt(short, long)]
+    out: Option<String>,
+    /// 音质：64k、132k或192k，默认选最好的
+    #[structopt(short, long)]
+    quality: Option<String>,
+    /// 同时下载的视频数，默认为1
+    #[structopt(short = "j", long)]
+    concurrency: Option<usize>,
+    /// 文件格式：aac（默认）、m4a直接保存，mp3、flac等需要ffmpeg
+    #[structopt(short, long)]
+    format: Option<String>,
+    /// 忽略清单，重新下载已下载的文件
+    #[structopt(long)]
+    force: bool,
+    /// 文件名模板，`/`会生成子目录。占位符有{title} {part} {part_name} {part_index}
+    /// {part_count} {upper} {upper_mid} {bvid} {cid} {fav_folder} {fav_id} {quality}
+    /// {pubdate:%Y} {fav_time:%Y-%m}，[...]中有占位符为空时整段省略，
+    /// 如"{upper}/{title}[ - {part}]"。默认为"{title} - {part_name} - {upper}"
+    #[structopt(short, long)]
+    template: Option<String>,
+    /// 文件名要兼容的文件系统，默认为windows，U盘用fat32
+    #[structopt(long = "fs", possible_values = &["posix", "windows", "fat32", "exfat"])]
+    naming: Option<Strictness>,
+    /// 文件名只用ASCII字符：汉字转拼音，假名转罗马字，原标题写入标签（需要ffmpeg）
+    #[structopt(long, overrides_with = "no-ascii")]
+    ascii: bool,
+    /// 不用--ascii，账号的默认设置中用了时关掉它
+    #[structopt(long, overrides_with = "ascii")]
+    no_ascii: bool,
+    /// 标题、分P名和UP主名转成简体（hans）或繁体（hant），默认不转换
+    #[structopt(long, possible_values = &["keep", "hans", "hant"])]
+    script: Option<Script>,
+    /// 用ffmpeg写入标题、UP主、收藏夹名等标签
+    #[structopt(long, overrides_with = "no-tags")]
+    tags: bool,
+    /// 不用--tags，账号的默认设置中用了时关掉它
+    #[structopt(long, overrides_with = "tags")]
+    no_tags: bool,
+    /// 只下载符合选择表达式的视频，如"0-19, 35, 50-"（list显示的下标）
+    /// 或"duration < 10min & upper = xxx"，逗号分隔的各项取并集
+    #[structopt(long)]
+    select: Option<String>,
+    /// 下载完后只重试失败的项的次数，不重新获取列表
+    #[structopt(long, default_value = "0")]
+    retries: usize,
+    /// 下载用的账号名，记录在清单里
+    #[structopt(skip)]
+    account: String,
+}
+
+#[derive(Clone, Copy)]
+enum Removed {
+    Report,
+    Archive,
+    Delete,
+}
+
+impl std::str::FromStr for Removed {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Removed, String> {
+        match s {
+            "report" => Ok(Removed::Report),
+            "archive" => Ok(Removed::Archive),
+            "delete" => Ok(Removed::Delete),
+            _ => Err(format!("未知的处理方式：{}", s)),
+        }
+    }
+}
+
+impl From<Removed> for MirrorAction {
+    fn from(r: Removed) -> MirrorAction {
+        match r {
+            Removed::Report => MirrorAction::Report,
+            Removed::Archive => MirrorAction::Archive,
+            Removed::Delete => MirrorAction::Delete,
+        }
+    }
+}
+
+impl DownArgs {
+    fn options(&self, fid: &str) -> Result<DownOptions, i32> {
+        let quality = match &self.quality {
+            Some(q) => match bapi::quality_id(q) {
+                Some(id) => Some(id),
+                None => {
+                    eprintln!("未知的音质：{}", q);
+                    return Err(EXIT_USAGE);
+                }
+            },
+            None => None,
+        };
+        let template = self.template.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+        if let Err(e) = Template::parse(template) {
+            eprintln!("{}", e);
+            return Err(EXIT_USAGE);
+        }
+        let script = self.script.unwrap_or(Script::Keep);
+        if script != Script::Keep && !translit::AVAILABLE {
+            eprintln!("没有启用transliterate feature，不能转换简繁");
+            return Err(EXIT_USAGE);
+        }
+        if let Some(Err(e)) = self.select.as_deref().map(Expr::parse) {
+            eprintln!("{}", e);
+            return Err(EXIT_USAGE);
+        }
+        if self.ascii && !translit::AVAILABLE {
+            eprintln!("没有启用transliterate feature，文件名中的汉字会被去掉");
+        }
+        let defaults = DownOptions::new(fid, self.out());
+        Ok(DownOptions {
+            force: self.force,
+            quality,
+            format: match &self.format {
+                Some(f) => f.trim_start_matches('.').to_lowercase(),
+                None => defaults.format.clone(),
+            },
+            concurrency: self.concurrency.unwrap_or(defaults.concurrency),
+            template: template.to_string(),
+            naming: self.naming.unwrap_or_default(),
+            ascii: self.ascii,
+            script,
+            tags: self.tags,
+            account: self.account.clone(),
+            ..defaults
+        })
+    }
+
+    fn out(&self) -> &str {
+        self.out.as_deref().unwrap_or(".")
+    }
+
+    /// 用账号的默认设置代替命令行上没有给出的选项
+    fn use_profile(&mut self, profile: &Profile) {
+        self.account = profile.name.clone();
+        let d = match &profile.defaults {
+            Some(d) => d,
+            None => return,
+        };
+        if self.out.is_none() && !d.path.is_empty() {
+            self.out = Some(d.path.clone());
+        }
+        if self.quality.is_none() {
+            self.quality = d.quality.map(bapi::quality_name);
+        }
+        self.concurrency = self.concurrency.or(Some(d.concurrency));
+        self.format = self.format.take().or_else(|| Some(d.format.clone()));
+        self.template = self.template.take().or_else(|| Some(d.template.clone()));
+        self.naming = self.naming.or(Some(d.naming));
+        self.script = self.script.or(Some(d.script));
+        // --no-ascii、--no-tags明确关掉时不用默认设置
+        if !self.no_ascii {
+            self.ascii |= d.ascii;
+        }
+        if !self.no_tags {
+            self.tags |= d.tags;
+        }
+    }
+
+    /// 下载`list`，失败的项按`--retries`重试
+    async fn run(
+        &self,
+        list: &[serde_json::Value],
+        cred: &Credential,
+        opts: DownOptions,
+    ) -> Result<Summary, i32> {
+        let mut summary = start(list, cred, opts.clone()).await?;
+        for i in 0..self.retries {
+            // 未登录和取消的重试也没用
+            let retry = Summary {
+                failed: summary
+                    .failed
+                    .iter()
+                    .filter(|f| !matches!(f.kind, Some(FailKind::NotLogin | FailKind::Cancelled)))
+                    .cloned()
+                    .collect(),
+                ..Summary::default()
+            };
+            if retry.failed.is_empty() {
+                break;
+            }
+            eprintln!("第{}次重试，{}个失败的项", i + 1, retry.failed.len());
+            let (videos, parts) = retry.retry_list(list, &opts.parts);
+            let opts = DownOptions {
+                parts,
+                ..opts.clone()
+            };
+            summary.update(start(&videos, cred, opts).await?);
+        }
+        Ok(summary)
+    }
+
+    /// 按`--select`筛选列表，表达式已经在`options`中检查过
+    fn selected(&self, list: Vec<serde_json::Value>) -> Vec<serde_json::Value> {
+        match self.select.as_deref().map(Expr::parse) {
+            Some(Ok(expr)) => expr.select(&list),
+            _ => list,
+        }
+    }
+}
+
+/// 要下载的目标
+enum Target {
+    Folder(String),
+    Video(String),
+}
+
+fn parse_target(s: &str) -> Option<Target> {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) {
+        return Some(Target::Folder(s.to_string()));
+    }
+    // 收藏夹链接：space.bilibili.com/<mid>/favlist?fid=<fid>或medialist/detail/ml<fid>
+    for key in &["fid=", "/ml"] {
+        if let Some(i) = s.find(key) {
+            let fid: String = s[i + key.len()..]
+                .chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            if !fid.is_empty() {
+                return Some(Target::Folder(fid));
+            }
+        }
+    }
+    let i = s.find("BV")?;
+    let bvid: String = s[i..]
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect();
+    if bvid.len() == 12 {
+        Some(Target::Video(bvid))
+    } else {
+        None
+    }
+}
+
+fn api_error(e: bapi::MyError) -> i32 {
+    eprintln!("{}", e);
+    if e.is_not_login() {
+        EXIT_NOT_LOGIN
+    } else {
+        EXIT_API
+    }
+}
+
+fn print_json(v: &serde_json::Value) {
+    println!("{}", serde_json::to_string_pretty(v).unwrap_or_default());
+}
+
+/// 解析命令行并执行，返回进程退出码
+pub async fn run() -> i32 {
+    let mut cli = match Cli::from_args_safe() {
+        Ok(cli) => cli,
+        Err(e) if e.use_stderr() => {
+            eprintln!("{}", e.message);
+            return EXIT_USAGE;
+        }
+        // --help和--version
+        Err(e) => e.exit(),
+    };
+    let mut accounts = Accounts::load();
+    let needs_login = !matches!(
+        cli.command,
+        Commands::Login { .. } | Commands::Accounts { .. }
+    );
+    let (mut cred, profile, vault) = if needs_login {
+        match credential(&cli, &mut accounts) {
+            Ok(c) => c,
+            Err(code) => return code,
+        }
+    } else {
+        (Credential::default(), None, None)
+    };
+    let mut saved = match (&profile, vault) {
+        (Some(p), Some(vault)) => Some(Saved {
+            name: p.name.clone(),
+            vault,
+        }),
+        _ => None,
+    };
+    if needs_login {
+        refresh(&mut cred, &mut saved).await;
+    }
+    if let Some(w) = account::expiry_warning(&cred) {
+        eprintln!("{}", w);
+    }
+    if let Some(profile) = &profile {
+        match &mut cli.command {
+            Commands::Download { down, .. }
+            | Commands::Sync { down, .. }
+            | Commands::Watch { down, .. } => down.use_profile(profile),
+            _ => {}
+        }
+    }
+    let cred = &cred;
+    let res = match &cli.command {
+        Commands::Login {
+            save,
+            cookies,
+            chromium_key,
+            refresh_token,
+        } => {
+            let key = ChromiumKey::parse(chromium_key.as_deref().unwrap_or_default());
+            let from = cookies
+                .as_deref()
+                .map(|p| (p, &key, refresh_token.as_deref().unwrap_or_default()));
+            login(save.as_deref(), from, &mut accounts, cli.json).await
+        }
+        Commands::ExportCookies { file } => export_cookies(file, cred, cli.json),
+        Commands::Accounts { command } => {
+            account_command(command, cli.sessdata.as_deref(), &mut accounts, cli.json).await
+        }
+        Commands::Folders => folders(cred, cli.json).await,
+        Commands::List { fid } => list(fid, cred, cli.json).await,
+        Commands::Download { targets, down } => download(targets, down, cred, cli.json).await,
+        Commands::Sync {
+            fids,
+            down,
+            removed,
+            dry_run,
+            preset,
+        } => {
+            let removed = (*removed).into();
+            let preset = preset.as_deref();
+            sync(fids, down, removed, *dry_run, preset, cred, cli.json).await
+        }
+        Commands::Watch {
+            fids,
+            down,
+            interval,
+            jitter,
+        } => watch(fids, down, *interval, *jitter, cred.clone(), saved.take()).await,
+    };
+    res.unwrap_or_else(|code| code)
+}
+
+/// 请求用的凭据和账号：`--account`指定的账号优先，其次是`--sessdata`，
+/// 都没有时用当前账号
+fn credential(
+    cli: &Cli,
+    accounts: &mut Accounts,
+) -> Result<(Credential, Option<Profile>, Option<Vault>), i32> {
+    let name = match (&cli.account, &cli.sessdata, accounts.current()) {
+        (Some(name), _, _) => name.clone(),
+        (None, Some(sessdata), _) => return Ok((Credential::from_sessdata(sessdata), None, None)),
+        (None, None, Some(p)) => p.name.clone(),
+        (None, None, None) => return Ok((Credential::default(), None, None)),
+    };
+    if accounts.get(&name).is_none() {
+        eprintln!("没有账号：{}，可以用`accounts list`查看", name);
+        return Err(EXIT_USAGE);
+    }
+    let vault = if vault::AVAILABLE && (Vault::exists() || accounts.has_plain_credentials()) {
+        Some(unlock(accounts)?)
+    } else {
+        None
+    };
+    let profile = accounts.get(&name).cloned().unwrap_or_default();
+    if profile.credential.is_empty() {
+        if vault::AVAILABLE {
+            eprintln!(
+                "账号{}没有保存的Cookie，请用`login --save {}`重新登录",
+                name, name
+            );
+        } else {
+            eprintln!("没有启用secure-store feature，账号的Cookie不会保存，请用--sessdata");
+        }
+        return Err(EXIT_NOT_LOGIN);
+    }
+    Ok((profile.credential.clone(), Some(profile), vault))
+}
+
+/// 用到的保存的账号，刷新Cookie后写回凭据库
+struct Saved {
+    name: String,
+    vault: Vault,
+}
+
+/// Cookie需要刷新时用refresh_token刷新，是保存的账号时写回凭据库。
+/// 刷新失败只提醒，旧的Cookie可能还能用
+async fn refresh(cred: &mut Credential, saved: &mut Option<Saved>) {
+    if cred.refresh_token.is_empty() {
+        return;
+    }
+    match bapi::refresh_credential(cred).await {
+        Ok(Some(new)) => {
+            *cred = new;
+            eprintln!("Cookie已刷新");
+            if let Some(s) = saved {
+                s.vault.insert(&s.name, cred.clone());
+                if let Err(e) = s.vault.save() {
+                    eprintln!("{}，下次启动时要重新登录", e);
+                }
+            }
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+/// 解锁凭据库并填入各账号的凭据，旧版本明文保存的凭据顺便迁移进去
+fn unlock(accounts: &mut Accounts) -> Result<Vault, i32> {
+    if !vault::AVAILABLE {
+        eprintln!("没有启用secure-store feature，不能保存账号的Cookie");
+        return Err(EXIT_USAGE);
+    }
+    let exists = Vault::exists();
+    let passphrase = if exists {
+        read_passphrase("输入口令解锁保存的账号：")?
+    } else {
+        let p = read_passphrase("设置用来加密保存Cookie的口令：")?;
+        if std::env::var_os(PASSPHRASE_ENV).is_none() && read_passphrase("再输入一次：")? != p
+        {
+            eprintln!("两次输入的口令不一样");
+            return Err(EXIT_USAGE);
+        }
+        p
+    };
+    let mut vault = Vault::unlock(&passphrase).map_err(|e| {
+        eprintln!("{}", e);
+        EXIT_USAGE
+    })?;
+    if accounts.unlock(&mut vault) {
+        // 先写入凭据库，再从账号文件中去掉明文
+        vault
+            .save()
+            .and_then(|_| {
+                accounts.forget_plain();
+                accounts.save().map_err(|e| e.to_string())
+            })
+            .map_err(|e| {
+                eprintln!("{}", e);
+                EXIT_FAILED
+            })?;
+        eprintln!("已把明文保存的Cookie移到加密的凭据库中");
+    }
+    Ok(vault)
+}
+
+/// 从`BILI_PASSPHRASE`或终端读入口令，终端中输入时不回显
+fn read_passphrase(prompt: &str) -> Result<String, i32> {
+    use std::io::IsTerminal;
+    if let Ok(p) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(p);
+    }
+    if !std::io::stdin().is_terminal() {
+        eprintln!("需要口令：在终端中运行，或设置{}", PASSPHRASE_ENV);
+        return Err(EXIT_USAGE);
+    }
+    eprint!("{}", prompt);
+    let echo_off = set_echo(false);
+    let mut line = String::new();
+    let res = std::io::stdin().read_line(&mut line);
+    if echo_off {
+        set_echo(true);
+        eprintln!();
+    }
+    res.map_err(|e| {
+        eprintln!("{}", e);
+        EXIT_USAGE
+    })?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+/// 用stty开关终端回显，没有stty（如Windows）时返回false，这时口令会显示出来
+fn set_echo(on: bool) -> bool {
+    std::process::Command::new("stty")
+        .arg(if on { "echo" } else { "-echo" })
+        .stdin(std::process::Stdio::inherit())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+/// 用nav接口检查凭据并保存为账号
+async fn save_account(
+    accounts: &mut Accounts,
+    name: &str,
+    cred: Credential,
+) -> Result<Profile, i32> {
+    let nav = bapi::get_nav(&cred).await.map_err(api_error)?;
+    let profile = Profile {
+        mid: nav["data"]["mid"].as_i64().unwrap_or_default(),
+        uname: nav["data"]["uname"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        ..Profile::new(name, cred.clone())
+    };
+    if vault::AVAILABLE {
+        // 先解锁，免得凭据库中旧的凭据覆盖新登录的
+        let mut vault = unlock(accounts)?;
+        vault.insert(name, cred);
+        vault.save().map_err(|e| {
+            eprintln!("{}", e);
+            EXIT_FAILED
+        })?;
+    } else {
+        eprintln!("没有启用secure-store feature，账号的Cookie不会保存");
+    }
+    accounts.upsert(profile.clone());
+    accounts.set_current(Some(name));
+    accounts.save().map_err(|e| {
+        eprintln!("账号保存失败：{}", e);
+        EXIT_FAILED
+    })?;
+    eprintln!("已保存账号{}（{}），并设为当前账号", name, profile.uname);
+    if let Some(w) = account::expiry_warning(&profile.credential) {
+        eprintln!("{}", w);
+    }
+    Ok(profile)
+}
+
+async fn account_command(
+    command: &AccountCommands,
+    sessdata: Option<&str>,
+    accounts: &mut Accounts,
+    json: bool,
+) -> Result<i32, i32> {
+    match command {
+        AccountCommands::List => {
+            let current = accounts.current().map(|p| p.name.clone());
+            if json {
+                let list: Vec<serde_json::Value> = accounts
+                    .profiles()
+                    .iter()
+                    .map(|p| {
+                        serde_json::json!({
+                            "name": p.name,
+                            "mid": p.mid,
+                            "uname": p.uname,
+                            "current": current.as_deref() == Some(p.name.as_str()),
+                            "defaults": p.defaults,
+                        })
+                    })
+                    .collect();
+                print_json(&serde_json::Value::from(list));
+            } else {
+                for p in accounts.profiles() {
+                    let mark = if current.as_deref() == Some(p.name.as_str()) {
+                        "*"
+                    } else {
+                        ""
+                    };
+                    println!("{}{}\t{}\t{}", mark, p.name, p.mid, p.uname);
+                }
+            }
+            Ok(0)
+        }
+        AccountCommands::Add { name } => {
+            let sessdata = match sessdata {
+                Some(s) => s,
+                None => {
+                    eprintln!("需要用--sessdata或BILI_SESSDATA给出Cookie，也可以用`login --save`");
+                    return Err(EXIT_USAGE);
+                }
+            };
+            save_account(accounts, name, Credential::from_sessdata(sessdata)).await?;
+            finish(true, json)
+        }
+        AccountCommands::Remove { name } => {
+            if accounts.get(name).is_none() {
+                eprintln!("没有账号：{}", name);
+                return Err(EXIT_USAGE);
+            }
+            if vault::AVAILABLE && Vault::exists() {
+                let mut vault = unlock(accounts)?;
+                vault.remove(name);
+                vault.save().map_err(|e| {
+                    eprintln!("{}", e);
+                    EXIT_FAILED
+                })?;
+            }
+            accounts.remove(name);
+            accounts.save().map_err(|e| {
+                eprintln!("账号保存失败：{}", e);
+                EXIT_FAILED
+            })?;
+            finish(true, json)
+        }
+        AccountCommands::Use { name } => {
+            if accounts.get(name).is_none() {
+                eprintln!("没有账号：{}", name);
+                return Err(EXIT_USAGE);
+            }
+            accounts.set_current(Some(name));
+            accounts.save().map_err(|e| {
+                eprintln!("账号保存失败：{}", e);
+                EXIT_FAILED
+            })?;
+            finish(true, json)
+        }
+        AccountCommands::Defaults { name, fid, down } => {
+            let opts = down.options(fid)?;
+            match accounts.get_mut(name) {
+                Some(p) => p.set_defaults(&opts),
+                None => {
+                    eprintln!("没有账号：{}", name);
+                    return Err(EXIT_USAGE);
+                }
+            }
+            accounts.save().map_err(|e| {
+                eprintln!("账号保存失败：{}", e);
+                EXIT_FAILED
+            })?;
+            finish(true, json)
+        }
+    }
+}
+
+/// `from`为要导入的Cookie文件和Chromium的密钥，没有时扫码登录
+async fn login(
+    save: Option<&str>,
+    from: Option<(&Path, &ChromiumKey, &str)>,
+    accounts: &mut Accounts,
+    json: bool,
+) -> Result<i32, i32> {
+    let cred = match from {
+        Some((path, key, refresh_token)) => {
+            let mut cred = cookies::import(path, key).map_err(|e| {
+                eprintln!("{}", e);
+                EXIT_USAGE
+            })?;
+            // 保存账号时会检查，不保存时也看一下Cookie是否还有效
+            if save.is_none() {
+                bapi::get_nav(&cred).await.map_err(api_error)?;
+            }
+            cred.refresh_token = refresh_token.to_string();
+            cred
+        }
+        None => Credential::from(qr_login().await?),
+    };
+    if let Some(name) = save {
+        save_account(accounts, name, cred.clone()).await?;
+    }
+    if json {
+        print_json(&serde_json::json!({
+            "SESSDATA": cred.sessdata,
+            "bili_jct": cred.bili_jct,
+            "DedeUserID": cred.dede_user_id,
+            "buvid3": cred.buvid3,
+            "refresh_token": cred.refresh_token,
+        }));
+    } else if save.is_none() {
+        println!("BILI_SESSDATA={}", cred.sessdata);
+        eprintln!("登录成功，可以执行 export BILI_SESSDATA=... 后使用其它命令");
+    }
+    Ok(0)
+}
+
+fn export_cookies(file: &Path, cred: &Credential, json: bool) -> Result<i32, i32> {
+    if cred.is_empty() {
+        eprintln!("没有登录，请用--account或--sessdata指定要导出的Cookie");
+        return Err(EXIT_NOT_LOGIN);
+    }
+    cookies::export(file, cred).map_err(|e| {
+        eprintln!("{}", e);
+        EXIT_FAILED
+    })?;
+    finish(true, json)
+}
+
+async fn qr_login() -> Result<bapi::LoginInfo, i32> {
+    let qr = bapi::qr_login_generate().await.map_err(api_error)?;
+    if let Ok(code) = qrcode::QrCode::new(&qr.url) {
+        use qrcode::render::unicode::Dense1x2;
+        eprintln!(
+            "{}",
+            code.render::<Dense1x2>()
+                .dark_color(Dense1x2::Light)
+                .light_color(Dense1x2::Dark)
+                .build()
+        );
+    }
+    eprintln!("用B站手机客户端扫描二维码，或打开：{}", qr.url);
+    loop {
+        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+        match bapi::qr_login_poll(&qr.qrcode_key)
+            .await
+            .map_err(api_error)?
+        {
+            bapi::QrStatus::NotScanned => {}
+            bapi::QrStatus::Scanned => eprintln!("已扫描，请在手机上确认"),
+            bapi::QrStatus::Expired => {
+                eprintln!("二维码已过期");
+                return Err(EXIT_FAILED);
+            }
+            bapi::QrStatus::Done(info) => return Ok(info),
+        }
+    }
+}
+
+async fn folders(cred: &Credential, json: bool) -> Result<i32, i32> {
+    let nav = bapi::get_nav(cred).await.map_err(api_error)?;
+    let mid = nav["data"]["mid"].as_i64().unwrap_or_default();
+    let list = bapi::get_fav_folders(mid, cred).await.map_err(api_error)?;
+    if json {
+        print_json(&serde_json::Value::from(list));
+    } else {
+        for f in list.iter() {
+            println!(
+                "{}\t{}\t{}",
+                f["id"],
+                f["media_count"],
+                f["title"].as_str().unwrap_or_default()
+            );
+        }
+    }
+    Ok(0)
+}
+
+async fn list(fid: &str, cred: &Credential, json: bool) -> Result<i32, i32> {
+    let list = bapi::get_fav_list(fid, cred).await.map_err(api_error)?;
+    if json {
+        print_json(&serde_json::Value::from(list));
+    } else {
+        for (i, v) in list.iter().enumerate() {
+            println!(
+                "{}\t{}\t{}\t{}",
+                i,
+                v["bvid"].as_str().unwrap_or_default(),
+                v["title"].as_str().unwrap_or_default(),
+                v["upper"]["name"].as_str().unwrap_or_default()
+            );
+        }
+    }
+    Ok(0)
+}
+
+async fn download(
+    targets: &[String],
+    down: &DownArgs,
+    cred: &Credential,
+    json: bool,
+) -> Result<i32, i32> {
+    // 先检查参数，免得获取完列表才报错
+    down.options("")?;
+    // 收藏夹逐个下载，单独给出的视频放在一起下载
+    let mut folders: Vec<String> = vec![];
+    let mut videos: Vec<serde_json::Value> = vec![];
+    for t in targets.iter() {
+        match parse_target(t) {
+            Some(Target::Folder(fid)) => folders.push(fid),
+            Some(Target::Video(bvid)) => {
+                let view = bapi::get_view(&bvid, cred).await.map_err(api_error)?;
+                let data = &view["data"];
+                videos.push(serde_json::json!({
+                    "bvid": bvid,
+                    "title": data["title"],
+                    "upper": data["owner"],
+                    "page": data["videos"],
+                    "pubtime": data["pubdate"],
+                }));
+            }
+            None => {
+                eprintln!("无法识别：{}", t);
+                return Err(EXIT_USAGE);
+            }
+        }
+    }
+    let mut summary = Summary::default();
+    let mut invalid = vec![];
+    for fid in folders.iter() {
+        let list = bapi::get_fav_list(fid, cred).await.map_err(api_error)?;
+        let list = down.selected(list);
+        summary.extend(down.run(&list, cred, down.options(fid)?).await?);
+        invalid.extend(report_invalid(fid, &list, down.out(), json));
+    }
+    let videos = down.selected(videos);
+    if !videos.is_empty() {
+        summary.extend(down.run(&videos, cred, down.options("")?).await?);
+    }
+    let ok = summary.ok();
+    report_summary(&summary, json);
+    if json {
+        print_json(&serde_json::json!({ "ok": ok, "invalid": invalid, "summary": summary }));
+        return Ok(if ok { 0 } else { EXIT_FAILED });
+    }
+    finish(ok, false)
+}
+
+async fn start(
+    list: &[serde_json::Value],
+    cred: &Credential,
+    opts: DownOptions,
+) -> Result<Summary, i32> {
+    start_download(list, cred, opts, None, Control::default())
+        .await
+        .map_err(|e| {
+            eprintln!("{}", e);
+            EXIT_USAGE
+        })
+}
+
+/// 输出失败的项，每行为`failed\tbvid\tcid\t类别\t标题\t原因`，统计在stderr
+fn report_summary(summary: &Summary, json: bool) {
+    if !json {
+        for i in &summary.failed {
+            let kind = i.kind.map(|k| k.to_string()).unwrap_or_default();
+            println!(
+                "failed\t{}\t{}\t{}\t{}\t{}",
+                i.bvid, i.cid, kind, i.title, i.detail
+            );
+        }
+    }
+    eprintln!("{}", summary);
+}
+
+/// 列出收藏夹中已失效的视频和本地以前下载的文件，返回JSON格式的报告
+fn report_invalid(
+    fid: &str,
+    list: &[serde_json::Value],
+    out: &str,
+    json: bool,
+) -> Vec<serde_json::Value> {
+    let manifest = Manifest::load(out);
+    manifest
+        .invalid(list)
+        .into_iter()
+        .map(|v| {
+            if !json {
+                let title = v.local.first().map(|e| e.title.as_str()).unwrap_or("");
+                let paths: Vec<&str> = v.local.iter().map(|e| e.path.as_str()).collect();
+                println!(
+                    "invalid\t{}\t{}\t{}\t{}",
+                    fid,
+                    v.bvid,
+                    title,
+                    paths.join("|")
+                );
+            }
+            serde_json::json!({
+                "fid": fid,
+                "bvid": v.bvid,
+                "index": v.index,
+                "fav_time": v.fav_time,
+                "local": v.local.iter().map(|e| serde_json::json!({
+                    "title": e.title,
+                    "part": e.part,
+                    "author": e.author,
+                    "path": e.path,
+                })).collect::<Vec<serde_json::Value>>(),
+            })
+        })
+        .collect()
+}
+
+async fn sync(
+    fids: &[String],
+    down: &DownArgs,
+    removed: MirrorAction,
+    dry_run: bool,
+    preset: Option<&str>,
+    cred: &Credential,
+    json: bool,
+) -> Result<i32, i32> {
+    down.options("")?;
+    let presets = Presets::load();
+    let mut selections = vec![];
+    for fid in fids.iter() {
+        match preset {
+            Some(name) => match presets.get(fid, name) {
+                Some(s) => selections.push(s.clone()),
+                None => {
+                    eprintln!("收藏夹{}没有预设：{}", fid, name);
+                    return Err(EXIT_USAGE);
+                }
+            },
+            None => selections.push(Selection::default()),
+        }
+    }
+    let mut summary = Summary::default();
+    let mut ok = true;
+    let mut report = vec![];
+    let mut invalid = vec![];
+    for (fid, selection) in fids.iter().zip(selections) {
+        let list = bapi::get_fav_list(fid, cred).await.map_err(api_error)?;
+        if !dry_run {
+            let opts = DownOptions {
+                parts: selection.parts().clone(),
+                ..down.options(fid)?
+            };
+            let down_list = selection.filter(&down.selected(list.clone()));
+            summary.extend(down.run(&down_list, cred, opts).await?);
+        }
+        let mut manifest = Manifest::load(down.out());
+        for (e, r) in manifest.mirror(fid, &list, removed, dry_run) {
+            if let Err(err) = &r {
+                ok = false;
+                eprintln!("{}：{}", e.path, err);
+            }
+            if !json {
+                println!("removed\t{}\t{}", fid, e.path);
+            }
+            report.push(serde_json::json!({
+                "fid": fid,
+                "bvid": e.bvid,
+                "path": e.path,
+                "ok": r.is_ok(),
+            }));
+        }
+        invalid.extend(report_invalid(fid, &list, down.out(), json));
+    }
+    ok &= summary.ok();
+    if !dry_run {
+        report_summary(&summary, json);
+    }
+    if json {
+        print_json(&serde_json::json!({
+            "ok": ok,
+            "removed": report,
+            "invalid": invalid,
+            "summary": summary,
+        }));
+        return Ok(if ok { 0 } else { EXIT_FAILED });
+    }
+    finish(ok, false)
+}
+
+async fn watch(
+    fids: &[String],
+    down: &DownArgs,
+    interval: u64,
+    jitter: u64,
+    mut cred: Credential,
+    mut saved: Option<Saved>,
+) -> Result<i32, i32> {
+    down.options("")?;
+    let mut state = WatchState::load(down.out());
+    loop {
+        let cred_ref = &cred;
+        for fid in fids.iter() {
+            let since = state.last(fid);
+            let opts = down.options(fid)?;
+            let sync = async {
+                // --select的下标按收藏夹原来的顺序算，和list命令、GUI中显示的一致，
+                // 所以这时要取完整的列表，先选择再去掉已经处理过的
+                let list = if down.select.is_some() {
+                    bapi::get_fav_list(fid, cred_ref).await?
+                } else {
+                    bapi::get_fav_list_since(fid, cred_ref, since).await?
+                };
+                let new: Vec<i64> = list
+                    .iter()
+                    .map(filter::fav_time)
+                    .filter(|t| *t > since)
+                    .collect();
+                eprintln!("收藏夹{}：{}个新视频", fid, new.len());
+                let latest = new.into_iter().max();
+                let mut list: Vec<serde_json::Value> = down
+                    .selected(list)
+                    .into_iter()
+                    .filter(|v| filter::fav_time(v) > since)
+                    .collect();
+                list.sort_by_key(filter::fav_time);
+                let ok = match down.run(&list, cred_ref, opts).await {
+                    Ok(summary) => {
+                        eprintln!("{}", summary);
+                        summary.ok()
+                    }
+                    Err(_) => false,
+                };
+                Ok::<Option<i64>, bapi::MyError>(if ok { latest } else { None })
+            };
+            tokio::select! {
+                r = sync => match r {
+                    // 有失败的就不前进，下次会重新列出这些视频，已下载的由清单跳过
+                    Ok(Some(latest)) => {
+                        state.set_last(fid, latest);
+                        if let Err(e) = state.save() {
+                            eprintln!("状态写入失败：{}", e);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => eprintln!("{}", e),
+                },
+                _ = shutdown_signal() => return Ok(0),
+            }
+        }
+        let delay = watch::next_delay(interval, jitter);
+        eprintln!("{}秒后再次检查", delay.as_secs());
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown_signal() => return Ok(0),
+        }
+        // 长时间运行时Cookie会过期，每次检查前看看是否需要刷新
+        refresh(&mut cred, &mut saved).await;
+    }
+}
+
+fn finish(ok: bool, json: bool) -> Result<i32, i32> {
+    if json {
+        print_json(&serde_json::json!({ "ok": ok }));
+    }
+    Ok(if ok { 0 } else { EXIT_FAILED })
+}
@@ -0,0 +1,6 @@
+mod cli;
+
+#[tokio::main]
+async fn main() {
+    std::process::exit(cli::run().await);
+}
@@ -0,0 +1,138 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// 控制进行中的下载：暂停、继续、取消，可以作用于整个队列或单个视频（bvid）。
+///
+/// 克隆后共用同一个状态，GUI留一份，下载引擎拿一份
+#[derive(Clone)]
+pub struct Control {
+    tx: Arc<Mutex<watch::Sender<State>>>,
+    /// 保证通道不会因为没有接收端而关闭
+    rx: watch::Receiver<State>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct State {
+    paused: bool,
+    cancelled: bool,
+    paused_items: HashSet<String>,
+    cancelled_items: HashSet<String>,
+}
+
+impl State {
+    fn is_paused(&self, bvid: &str) -> bool {
+        self.paused || self.paused_items.contains(bvid)
+    }
+
+    fn is_cancelled(&self, bvid: &str) -> bool {
+        self.cancelled || self.cancelled_items.contains(bvid)
+    }
+}
+
+impl Default for Control {
+    fn default() -> Control {
+        let (tx, rx) = watch::channel(State::default());
+        Control {
+            tx: Arc::new(Mutex::new(tx)),
+            rx,
+        }
+    }
+}
+
+impl Control {
+    fn modify(&self, f: impl FnOnce(&mut State)) {
+        let tx = self.tx.lock().unwrap();
+        let mut state = self.rx.borrow().clone();
+        f(&mut state);
+        let _ = tx.send(state);
+    }
+
+    pub fn pause(&self) {
+        self.modify(|s| s.paused = true);
+    }
+
+    pub fn resume(&self) {
+        self.modify(|s| s.paused = false);
+    }
+
+    pub fn cancel(&self) {
+        self.modify(|s| s.cancelled = true);
+    }
+
+    pub fn pause_item(&self, bvid: &str) {
+        self.modify(|s| {
+            s.paused_items.insert(bvid.to_string());
+        });
+    }
+
+    pub fn resume_item(&self, bvid: &str) {
+        self.modify(|s| {
+            s.paused_items.remove(bvid);
+        });
+    }
+
+    pub fn cancel_item(&self, bvid: &str) {
+        self.modify(|s| {
+            s.cancelled_items.insert(bvid.to_string());
+        });
+    }
+
+    /// 整个队列是否暂停
+    pub fn is_queue_paused(&self) -> bool {
+        self.rx.borrow().paused
+    }
+
+    pub fn is_queue_cancelled(&self) -> bool {
+        self.rx.borrow().cancelled
+    }
+
+    /// 这个视频是否单独暂停
+    pub fn is_item_paused(&self, bvid: &str) -> bool {
+        self.rx.borrow().paused_items.contains(bvid)
+    }
+
+    /// 这个视频是否暂停，包括整个队列暂停
+    pub fn is_paused(&self, bvid: &str) -> bool {
+        self.rx.borrow().is_paused(bvid)
+    }
+
+    pub fn is_cancelled(&self, bvid: &str) -> bool {
+        self.rx.borrow().is_cancelled(bvid)
+    }
+
+    /// 暂停时等到继续为止，已取消时返回false
+    pub async fn proceed(&self, bvid: &str) -> bool {
+        let mut rx = self.rx.clone();
+        loop {
+            {
+                let state = rx.borrow_and_update();
+                if state.is_cancelled(bvid) {
+                    return false;
+                }
+                if !state.is_paused(bvid) {
+                    return true;
+                }
+            }
+            if rx.changed().await.is_err() {
+                return true;
+            }
+        }
+    }
+
+    /// 在这个视频被暂停或取消时完成，用来打断进行中的请求
+    pub async fn interrupted(&self, bvid: &str) {
+        let mut rx = self.rx.clone();
+        loop {
+            {
+                let state = rx.borrow_and_update();
+                if state.is_paused(bvid) || state.is_cancelled(bvid) {
+                    return;
+                }
+            }
+            if rx.changed().await.is_err() {
+                return futures::future::pending().await;
+            }
+        }
+    }
+}
@@ -0,0 +1,479 @@
+use crate::bapi::Credential;
+use std::path::Path;
+
+/// 编译时是否启用了`browser-cookies` feature。没有启用时只能读写cookies.txt
+pub const BROWSER_AVAILABLE: bool = cfg!(feature = "browser-cookies");
+
+/// 只取这个域名及其子域名的Cookie
+const DOMAIN: &str = "bilibili.com";
+
+/// Chromium加密Cookie用的密钥
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChromiumKey {
+    /// 没有加密，或Linux上没有密钥环时的默认密码
+    None,
+    /// Windows上`Local State`中用DPAPI解开后的32字节AES-256-GCM密钥
+    Aes(Vec<u8>),
+    /// Linux密钥环或macOS钥匙串中的“Chrome Safe Storage”密码
+    Password(String),
+}
+
+impl ChromiumKey {
+    /// 64位十六进制是AES密钥，其它非空的是密码
+    pub fn parse(s: &str) -> ChromiumKey {
+        let s = s.trim();
+        if s.is_empty() {
+            return ChromiumKey::None;
+        }
+        match decode_hex(s) {
+            Some(key) if key.len() == 32 => ChromiumKey::Aes(key),
+            _ => ChromiumKey::Password(s.to_string()),
+        }
+    }
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// 一条Cookie，`expires`为Unix时间戳（秒），会话Cookie为0
+struct Cookie {
+    host: String,
+    name: String,
+    value: String,
+    expires: i64,
+}
+
+/// 从Cookie中取出B站的登录凭据，同名的取过期最晚的
+fn collect(cookies: Vec<Cookie>) -> Result<Credential, String> {
+    let mut cred = Credential::default();
+    let mut latest = std::collections::HashMap::new();
+    for c in cookies {
+        let host = c.host.trim_start_matches('.');
+        if host != DOMAIN && !host.ends_with(&format!(".{}", DOMAIN)) {
+            continue;
+        }
+        let newest = latest.entry(c.name.clone()).or_insert(i64::MIN);
+        if c.expires < *newest {
+            continue;
+        }
+        *newest = c.expires;
+        // 浏览器中保存的是编码后的值，`Credential`中保存原值
+        let value = urlencoding::decode(&c.value).unwrap_or(c.value);
+        match c.name.as_str() {
+            "SESSDATA" => {
+                cred.sessdata = value;
+                cred.expires = c.expires;
+            }
+            "bili_jct" => cred.bili_jct = value,
+            "DedeUserID" => cred.dede_user_id = value,
+            "buvid3" => cred.buvid3 = value,
+            _ => {}
+        }
+    }
+    if cred.is_empty() {
+        return Err(String::from(
+            "没有找到bilibili.com的SESSDATA，请先在浏览器中登录B站",
+        ));
+    }
+    Ok(cred)
+}
+
+/// 读取Cookie文件：按内容判断是Firefox或Chromium的数据库，还是Netscape格式的cookies.txt
+pub fn import(path: &Path, key: &ChromiumKey) -> Result<Credential, String> {
+    let data = std::fs::read(path).map_err(|e| format!("Cookie文件读取失败：{}", e))?;
+    if data.starts_with(b"SQLite format 3\0") {
+        return browser::import(path, key);
+    }
+    let text = String::from_utf8_lossy(&data);
+    collect(parse_cookies_txt(&text))
+}
+
+fn parse_cookies_txt(text: &str) -> Vec<Cookie> {
+    text.lines()
+        .filter_map(|line| {
+            // curl等工具把HttpOnly的Cookie写成注释
+            let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+            if line.starts_with('#') {
+                return None;
+            }
+            let fields: Vec<&str> = line.trim_end_matches('\r').split('\t').collect();
+            match fields[..] {
+                [host, _, _, _, expires, name, value] => Some(Cookie {
+                    host: host.to_string(),
+                    name: name.to_string(),
+                    value: value.to_string(),
+                    expires: expires.parse().unwrap_or_default(),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// 导出为Netscape格式的cookies.txt，可以给curl、yt-dlp等工具用
+pub fn export(path: &Path, cred: &Credential) -> Result<(), String> {
+    let expires = cred.expires_at().unwrap_or_default();
+    let mut text = String::from("# Netscape HTTP Cookie File\n");
+    for (name, value, http_only) in [
+        ("SESSDATA", &cred.sessdata, true),
+        ("bili_jct", &cred.bili_jct, false),
+        ("DedeUserID", &cred.dede_user_id, false),
+        ("buvid3", &cred.buvid3, false),
+    ] {
+        if value.is_empty() {
+            continue;
+        }
+        text.push_str(&format!(
+            "{}.{}\tTRUE\t/\tFALSE\t{}\t{}\t{}\n",
+            if http_only { "#HttpOnly_" } else { "" },
+            DOMAIN,
+            expires,
+            name,
+            urlencoding::encode(value)
+        ));
+    }
+    crate::dirs::write_private(path, text.as_bytes())
+        .map_err(|e| format!("cookies.txt写入失败：{}", e))
+}
+
+#[cfg(feature = "browser-cookies")]
+mod browser {
+    use super::{collect, ChromiumKey, Cookie, DOMAIN};
+    use crate::bapi::Credential;
+    use rusqlite::{Connection, OpenFlags};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Chromium的`expires_utc`从1601-01-01起算（微秒）
+    const WINDOWS_EPOCH_OFFSET: i64 = 11_644_473_600;
+
+    /// 浏览器运行时数据库是锁着的，复制一份再读。
+    ///
+    /// 数据库是WAL模式，刚登录时新的Cookie可能还只在`-wal`文件里，
+    /// 所以`-wal`和`-shm`也一起复制，打开副本时SQLite会把它们合并进来
+    pub fn import(path: &Path, key: &ChromiumKey) -> Result<Credential, String> {
+        // 同时导入时各用各的目录
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "bili_music_download_cookies_{}_{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let res = copy(path, &dir).and_then(|tmp| read(&tmp, key));
+        let _ = std::fs::remove_dir_all(&dir);
+        res
+    }
+
+    /// 复制到只有自己能读的临时目录中，返回复制后的数据库路径
+    fn copy(path: &Path, dir: &Path) -> Result<std::path::PathBuf, String> {
+        let error = |e: std::io::Error| format!("Cookie数据库复制失败：{}", e);
+        let _ = std::fs::remove_dir_all(dir);
+        let mut builder = std::fs::DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder.create(dir).map_err(error)?;
+        let tmp = dir.join("cookies.sqlite");
+        std::fs::copy(path, &tmp).map_err(error)?;
+        for suffix in ["-wal", "-shm"] {
+            let mut from = path.as_os_str().to_os_string();
+            from.push(suffix);
+            let mut to = tmp.as_os_str().to_os_string();
+            to.push(suffix);
+            match std::fs::copy(&from, &to) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(error(e)),
+                _ => {}
+            }
+        }
+        Ok(tmp)
+    }
+
+    fn read(path: &Path, key: &ChromiumKey) -> Result<Credential, String> {
+        // 副本要能写，SQLite才能读入-wal中的内容
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE)
+            .map_err(|e| format!("Cookie数据库打开失败：{}", e))?;
+        let is_firefox: bool = conn
+            .query_row(
+                "SELECT count(*) > 0 FROM sqlite_master WHERE name = 'moz_cookies'",
+                [],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        let cookies = if is_firefox {
+            read_firefox(&conn)
+        } else {
+            read_chromium(&conn, key)
+        };
+        collect(cookies.map_err(|e| format!("Cookie数据库读取失败：{}", e))?)
+    }
+
+    fn read_firefox(conn: &Connection) -> Result<Vec<Cookie>, String> {
+        let mut stmt = conn
+            .prepare("SELECT host, name, value, expiry FROM moz_cookies WHERE host LIKE ?1")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([format!("%{}", DOMAIN)], |row| {
+                let expiry: i64 = row.get(3)?;
+                Ok(Cookie {
+                    host: row.get(0)?,
+                    name: row.get(1)?,
+                    value: row.get(2)?,
+                    // 新版本的Firefox以毫秒保存
+                    expires: if expiry > 100_000_000_000 {
+                        expiry / 1000
+                    } else {
+                        expiry
+                    },
+                })
+            })
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+    }
+
+    fn read_chromium(conn: &Connection, key: &ChromiumKey) -> Result<Vec<Cookie>, String> {
+        // 版本24起，解密后的值前面有域名的SHA256
+        let version: i64 = conn
+            .query_row("SELECT value FROM meta WHERE key = 'version'", [], |row| {
+                row.get::<_, String>(0)
+            })
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+        let mut stmt = conn
+            .prepare(
+                "SELECT host_key, name, value, encrypted_value, expires_utc \
+                 FROM cookies WHERE host_key LIKE ?1",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([format!("%{}", DOMAIN)], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Vec<u8>>(3)?,
+                    row.get::<_, i64>(4)?,
+                ))
+            })
+            .map_err(|e| e.to_string())?;
+        let mut cookies = vec![];
+        for row in rows {
+            let (host, name, value, encrypted, expires_utc) = row.map_err(|e| e.to_string())?;
+            let value = if !value.is_empty() || encrypted.is_empty() {
+                value
+            } else {
+                let mut plain = decrypt(&encrypted, key)
+                    .map_err(|e| format!("Cookie {}解密失败：{}", name, e))?;
+                if version >= 24 && plain.len() >= 32 {
+                    plain.drain(..32);
+                }
+                String::from_utf8(plain)
+                    .map_err(|_| format!("Cookie {}解密失败，密钥可能不对", name))?
+            };
+            cookies.push(Cookie {
+                host,
+                name,
+                value,
+                expires: if expires_utc > 0 {
+                    expires_utc / 1_000_000 - WINDOWS_EPOCH_OFFSET
+                } else {
+                    0
+                },
+            });
+        }
+        Ok(cookies)
+    }
+
+    /// `v10`/`v11`开头：Windows上是AES-256-GCM，Linux和macOS上是
+    /// 用PBKDF2-SHA1从密码派生密钥的AES-128-CBC
+    fn decrypt(data: &[u8], key: &ChromiumKey) -> Result<Vec<u8>, String> {
+        let (prefix, body) = data.split_at(3.min(data.len()));
+        if prefix != b"v10" && prefix != b"v11" {
+            return Err(String::from(
+                "不认识的加密格式，Windows上旧版本的Cookie需要DPAPI",
+            ));
+        }
+        match key {
+            ChromiumKey::Aes(key) => decrypt_gcm(key, body),
+            ChromiumKey::Password(password) => decrypt_cbc(password, body),
+            // 没有密钥环时Linux上的Chromium用固定的密码
+            ChromiumKey::None if prefix == b"v10" => decrypt_cbc("peanuts", body),
+            ChromiumKey::None => Err(String::from("Cookie是加密的，需要提供密钥或密码")),
+        }
+    }
+
+    fn decrypt_gcm(key: &[u8], body: &[u8]) -> Result<Vec<u8>, String> {
+        use aes_gcm::aead::{Aead, KeyInit};
+        use aes_gcm::{Aes256Gcm, Nonce};
+        if body.len() < 12 {
+            return Err(String::from("数据太短"));
+        }
+        let (nonce, sealed) = body.split_at(12);
+        Aes256Gcm::new_from_slice(key)
+            .map_err(|_| String::from("密钥长度不对"))?
+            .decrypt(Nonce::from_slice(nonce), sealed)
+            .map_err(|_| String::from("密钥不对"))
+    }
+
+    fn decrypt_cbc(password: &str, body: &[u8]) -> Result<Vec<u8>, String> {
+        use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+        let iterations = if cfg!(target_os = "macos") { 1003 } else { 1 };
+        let mut key = [0; 16];
+        pbkdf2::pbkdf2_hmac::<sha1::Sha1>(password.as_bytes(), b"saltysalt", iterations, &mut key);
+        let mut buf = body.to_vec();
+        let plain = cbc::Decryptor::<aes::Aes128>::new_from_slices(&key, &[b' '; 16])
+            .map_err(|_| String::from("密钥长度不对"))?
+            .decrypt_padded_mut::<Pkcs7>(&mut buf)
+            .map_err(|_| String::from("密码不对"))?;
+        Ok(plain.to_vec())
+    }
+}
+
+#[cfg(not(feature = "browser-cookies"))]
+mod browser {
+    use super::ChromiumKey;
+    use crate::bapi::Credential;
+    use std::path::Path;
+
+    pub fn import(_path: &Path, _key: &ChromiumKey) -> Result<Credential, String> {
+        Err(String::from(
+            "没有启用browser-cookies feature，只能导入cookies.txt",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chromium_keys() {
+        assert_eq!(ChromiumKey::parse(" "), ChromiumKey::None);
+        assert_eq!(
+            ChromiumKey::parse(&"ab".repeat(32)),
+            ChromiumKey::Aes(vec![0xab; 32])
+        );
+        // 长度不对或不是十六进制的都当作密码
+        assert_eq!(
+            ChromiumKey::parse(&"ab".repeat(16)),
+            ChromiumKey::Password("ab".repeat(16))
+        );
+        assert_eq!(
+            ChromiumKey::parse(&"汉".repeat(32)),
+            ChromiumKey::Password("汉".repeat(32))
+        );
+    }
+
+    #[test]
+    fn cookies_txt_lines() {
+        let text = "# Netscape HTTP Cookie File\n\
+                    \n\
+                    #HttpOnly_.bilibili.com\tTRUE\t/\tFALSE\t100\tSESSDATA\told\r\n\
+                    .bilibili.com\tTRUE\t/\tFALSE\t200\tSESSDATA\ta%2Cb\n\
+                    .bilibili.com\tTRUE\t/\tFALSE\tnever\tbili_jct\tjct\n\
+                    .bilibili.com\tTRUE\t/\tFALSE\t200\tDedeUserID\n\
+                    .bilibili.com TRUE / FALSE 200 buvid3 spaces\n\
+                    .notbilibili.com\tTRUE\t/\tFALSE\t300\tSESSDATA\tother\n\
+                    www.bilibili.com\tFALSE\t/\tFALSE\t0\tbuvid3\tb3\n";
+        let cred = collect(parse_cookies_txt(text)).unwrap();
+        assert_eq!(cred.sessdata, "a,b");
+        assert_eq!(cred.expires, 200);
+        // 过期时间不是数字时当作会话Cookie
+        assert_eq!(cred.bili_jct, "jct");
+        assert_eq!(cred.dede_user_id, "");
+        assert_eq!(cred.buvid3, "b3");
+    }
+
+    #[test]
+    fn no_sessdata() {
+        assert!(collect(parse_cookies_txt("garbage\n\t\t\n")).is_err());
+    }
+
+    #[test]
+    fn export_and_import() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cookies.txt");
+        let cred = Credential {
+            sessdata: String::from("a,b*c"),
+            bili_jct: String::from("jct"),
+            expires: 1234,
+            ..Default::default()
+        };
+        export(&path, &cred).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let back = import(&path, &ChromiumKey::None).unwrap();
+        assert_eq!(back, cred);
+    }
+
+    #[cfg(feature = "browser-cookies")]
+    #[test]
+    fn firefox_cookies_still_in_the_wal() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cookies.sqlite");
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA wal_autocheckpoint = 0;
+             CREATE TABLE moz_cookies (host TEXT, name TEXT, value TEXT, expiry INTEGER);
+             INSERT INTO moz_cookies VALUES ('.bilibili.com', 'SESSDATA', 'x%2Cy', 1700000000000);",
+        )
+        .unwrap();
+        // 连接还开着，新写入的行只在-wal中
+        assert!(dir.path().join("cookies.sqlite-wal").exists());
+        let cred = import(&path, &ChromiumKey::None).unwrap();
+        assert_eq!(cred.sessdata, "x,y");
+        assert_eq!(cred.expires, 1700000000);
+        drop(conn);
+    }
+
+    #[cfg(all(feature = "browser-cookies", not(target_os = "macos")))]
+    #[test]
+    fn chromium_v10_cookies() {
+        use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+        let mut key = [0; 16];
+        pbkdf2::pbkdf2_hmac::<sha1::Sha1>(b"peanuts", b"saltysalt", 1, &mut key);
+        let value = b"secret";
+        let mut buf = [0; 16];
+        buf[..value.len()].copy_from_slice(value);
+        let sealed = cbc::Encryptor::<aes::Aes128>::new_from_slices(&key, &[b' '; 16])
+            .unwrap()
+            .encrypt_padded_mut::<Pkcs7>(&mut buf, value.len())
+            .unwrap();
+        let encrypted = [&b"v10"[..], sealed].concat();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Cookies");
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE meta (key TEXT, value TEXT);
+             INSERT INTO meta VALUES ('version', '18');
+             CREATE TABLE cookies (host_key TEXT, name TEXT, value TEXT,
+                                   encrypted_value BLOB, expires_utc INTEGER);",
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO cookies VALUES ('.bilibili.com', 'SESSDATA', '', ?1, 13348000000000000)",
+            [&encrypted],
+        )
+        .unwrap();
+        drop(conn);
+        let cred = import(&path, &ChromiumKey::None).unwrap();
+        assert_eq!(cred.sessdata, "secret");
+        assert_eq!(cred.expires, 13348000000 - 11_644_473_600);
+        assert!(import(&path, &ChromiumKey::Password(String::from("wrong"))).is_err());
+    }
+}
@@ -0,0 +1,39 @@
+use crate::dirs::cache_dir;
+use std::path::PathBuf;
+
+/// 缩略图的大小，B站的图片服务器按URL后缀缩放
+const THUMB_SUFFIX: &str = "@96w_60h_1c.jpg";
+
+/// 取视频封面的缩略图，缓存在`cache_dir()/covers`中，返回图片文件的路径
+pub async fn get_cover(url: &str) -> Result<PathBuf, String> {
+    // 封面的文件名是图片内容的哈希，可以直接作为缓存的键
+    let name = url
+        .rsplit('/')
+        .next()
+        .filter(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_alphanumeric() || c == '.'))
+        .ok_or_else(|| format!("无法识别的封面地址：{}", url))?;
+    let dir = cache_dir()
+        .ok_or_else(|| String::from("找不到缓存目录"))?
+        .join("covers");
+    let path = dir.join(format!("{}.jpg", name));
+    if path.exists() {
+        return Ok(path);
+    }
+    let body = reqwest::Client::new()
+        .get(format!("{}{}", url.replacen("http://", "https://", 1), THUMB_SUFFIX))
+        .header("Referer", "https://www.bilibili.com")
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("封面下载失败：{}", e))?
+        .bytes()
+        .await
+        .map_err(|e| format!("封面下载失败：{}", e))?;
+    std::fs::create_dir_all(&dir).map_err(|e| format!("目录创建失败：{}", e))?;
+    // 先写临时文件，避免同时加载时读到一半的图片
+    let tmp = dir.join(format!("{}.jpg.tmp", name));
+    std::fs::write(&tmp, &body)
+        .and_then(|_| std::fs::rename(&tmp, &path))
+        .map_err(|e| format!("封面写入失败：{}", e))?;
+    Ok(path)
+}
@@ -0,0 +1,52 @@
+use std::path::{Path, PathBuf};
+
+/// 程序自己的子目录名
+const APP_DIR: &str = "bili_music_download";
+
+/// `$xdg/bili_music_download`，没有时Windows上用`%windows%`，其它系统用`~/home`
+fn app_dir(xdg: &str, windows: &str, home: &str) -> Option<PathBuf> {
+    let base = std::env::var_os(xdg)
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os(windows).map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(home)))?;
+    Some(base.join(APP_DIR))
+}
+
+/// 配置目录：`$XDG_CONFIG_HOME/bili_music_download`，
+/// 没有时用`~/.config/bili_music_download`，Windows上用`%APPDATA%`
+pub fn config_dir() -> Option<PathBuf> {
+    app_dir("XDG_CONFIG_HOME", "APPDATA", ".config")
+}
+
+/// 缓存目录：`$XDG_CACHE_HOME/bili_music_download`，没有时用`~/.cache/bili_music_download`，
+/// Windows上用`%LOCALAPPDATA%`
+pub fn cache_dir() -> Option<PathBuf> {
+    app_dir("XDG_CACHE_HOME", "LOCALAPPDATA", ".cache")
+}
+
+/// 数据目录：`$XDG_DATA_HOME/bili_music_download`，
+/// 没有时用`~/.local/share/bili_music_download`，Windows上用`%APPDATA%`
+pub fn data_dir() -> Option<PathBuf> {
+    app_dir("XDG_DATA_HOME", "APPDATA", ".local/share")
+}
+
+/// 写入只有自己能读写的文件（unix上为0600），如保存Cookie的文件。
+/// 先写临时文件再改名，临时文件创建时就是0600，中途也不会被别人读到
+pub fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    let tmp = path.with_file_name(name);
+    // 上次留下的临时文件可能是别的权限
+    let _ = std::fs::remove_file(&tmp);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(&tmp)?.write_all(data)?;
+    std::fs::rename(&tmp, path)
+}
@@ -0,0 +1,736 @@
+use crate::bapi;
+use crate::control::Control;
+use crate::filter;
+use crate::manifest::{self, Manifest};
+use crate::sanitize::{self, Strictness};
+use crate::template::{Template, Vars, DEFAULT_TEMPLATE};
+use crate::translit::{self, Script};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::Mutex;
+
+/// 下载过程中发出的事件，GUI用来实时显示进度
+#[derive(Clone, Debug)]
+pub enum DownEvent {
+    /// 开始下载，共`videos`个视频
+    Started { videos: usize },
+    /// 分P的状态变化。`cid`为0时是还没取得分P列表的整个视频
+    Part {
+        bvid: String,
+        cid: i64,
+        state: PartState,
+    },
+    /// 一个视频处理完（包括跳过）
+    VideoDone { bvid: String, ok: bool },
+}
+
+/// 队列中一个分P的状态
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PartState {
+    /// 加入队列，`title`为视频标题（多P时加上分P名）
+    Queued {
+        title: String,
+    },
+    /// 获取音频地址
+    Resolving,
+    /// 已下载的字节数，`total`为服务器给出的文件大小
+    Downloading {
+        bytes: u64,
+        total: Option<u64>,
+    },
+    /// ffmpeg转换格式、写入标签
+    PostProcessing,
+    /// 暂停中，已下载的部分留在`.part`文件里
+    Paused,
+    Cancelled,
+    /// 下载完成，`path`为相对于输出目录的路径
+    Done {
+        path: String,
+    },
+    Skipped {
+        reason: String,
+    },
+    Failed {
+        reason: String,
+    },
+}
+
+impl std::fmt::Display for PartState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PartState::Queued { .. } => f.write_str("等待中"),
+            PartState::Resolving => f.write_str("获取地址"),
+            PartState::Downloading { bytes, total } => {
+                let mb = |n: u64| n as f64 / (1 << 20) as f64;
+                match total {
+                    Some(t) => write!(f, "下载中 {:.1}/{:.1} MB", mb(*bytes), mb(*t)),
+                    None => write!(f, "下载中 {:.1} MB", mb(*bytes)),
+                }
+            }
+            PartState::PostProcessing => f.write_str("处理中"),
+            PartState::Paused => f.write_str("已暂停"),
+            PartState::Cancelled => f.write_str("已取消"),
+            PartState::Done { .. } => f.write_str("完成"),
+            PartState::Skipped { reason } => write!(f, "跳过：{}", reason),
+            PartState::Failed { reason } => write!(f, "失败：{}", reason),
+        }
+    }
+}
+
+/// 失败的类别
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailKind {
+    /// 连接失败、超时等
+    Network,
+    /// SESSDATA失效，重试也没用
+    NotLogin,
+    /// 接口返回错误，如视频不可用、没有音频流
+    Api,
+    /// 创建目录、写入文件失败
+    File,
+    Ffmpeg,
+    Cancelled,
+}
+
+impl std::fmt::Display for FailKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FailKind::Network => "网络错误",
+            FailKind::NotLogin => "未登录",
+            FailKind::Api => "接口错误",
+            FailKind::File => "文件错误",
+            FailKind::Ffmpeg => "ffmpeg错误",
+            FailKind::Cancelled => "已取消",
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Failure {
+    kind: FailKind,
+    reason: String,
+}
+
+impl Failure {
+    fn new(kind: FailKind, reason: impl Into<String>) -> Failure {
+        Failure {
+            kind,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl From<bapi::MyError> for Failure {
+    fn from(e: bapi::MyError) -> Failure {
+        let kind = match &e {
+            bapi::MyError::ReqError(_) => FailKind::Network,
+            e if e.is_not_login() => FailKind::NotLogin,
+            bapi::MyError::BiliError(b) if b.code() == -1 || b.code() == -2 => FailKind::File,
+            _ => FailKind::Api,
+        };
+        Failure::new(kind, e.to_string())
+    }
+}
+
+/// 下载结果中的一项
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Item {
+    pub bvid: String,
+    /// 0表示整个视频，如取分P列表失败
+    pub cid: i64,
+    pub title: String,
+    /// 成功时为相对于输出目录的路径，跳过和失败时为原因
+    pub detail: String,
+    /// 失败的类别，成功和跳过时为None
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<FailKind>,
+}
+
+/// 一次下载的结果
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Summary {
+    pub succeeded: Vec<Item>,
+    pub skipped: Vec<Item>,
+    pub failed: Vec<Item>,
+}
+
+impl Summary {
+    /// 没有失败（包括取消）的项
+    pub fn ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    /// 失败的项按类别计数
+    pub fn failure_counts(&self) -> Vec<(FailKind, usize)> {
+        let mut counts: Vec<(FailKind, usize)> = vec![];
+        for kind in self.failed.iter().filter_map(|i| i.kind) {
+            match counts.iter_mut().find(|(k, _)| *k == kind) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((kind, 1)),
+            }
+        }
+        counts.sort();
+        counts
+    }
+
+    /// 合并重试的结果：重试过的视频原来的失败项换成新的结果
+    pub fn update(&mut self, retried: Summary) {
+        let bvids: HashSet<&str> = retried
+            .succeeded
+            .iter()
+            .chain(&retried.skipped)
+            .chain(&retried.failed)
+            .map(|i| i.bvid.as_str())
+            .collect();
+        self.failed.retain(|i| !bvids.contains(i.bvid.as_str()));
+        self.extend(retried);
+    }
+
+    pub fn extend(&mut self, other: Summary) {
+        self.succeeded.extend(other.succeeded);
+        self.skipped.extend(other.skipped);
+        self.failed.extend(other.failed);
+    }
+
+    /// 只重试失败的项：返回`list`中有失败的视频，以及给`DownOptions::parts`用的分P。
+    /// 整个视频失败时按`parts`原来的选择重新下载
+    pub fn retry_list(
+        &self,
+        list: &[serde_json::Value],
+        parts: &HashMap<String, HashSet<i64>>,
+    ) -> (Vec<serde_json::Value>, HashMap<String, HashSet<i64>>) {
+        let mut failed: HashMap<&str, HashSet<i64>> = HashMap::new();
+        for i in &self.failed {
+            failed.entry(&i.bvid).or_default().insert(i.cid);
+        }
+        let mut parts = parts.clone();
+        for (bvid, cids) in &failed {
+            if !cids.contains(&0) {
+                parts.insert(bvid.to_string(), cids.clone());
+            }
+        }
+        let videos = list
+            .iter()
+            .filter(|v| failed.contains_key(filter::bvid(v)))
+            .cloned()
+            .collect();
+        (videos, parts)
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "成功{}个，跳过{}个，失败{}个",
+            self.succeeded.len(),
+            self.skipped.len(),
+            self.failed.len()
+        )?;
+        let counts = self.failure_counts();
+        if !counts.is_empty() {
+            let counts: Vec<String> = counts
+                .iter()
+                .map(|(k, n)| format!("{}{}个", k, n))
+                .collect();
+            write!(f, "（{}）", counts.join("，"))?;
+        }
+        Ok(())
+    }
+}
+
+/// 一项的结果，用`Job::finish`记录
+enum Outcome {
+    Done(String),
+    Skipped(String),
+    Failed(Failure),
+}
+
+/// 下载选项。GUI里音质、格式和并发数用默认值，其它的由界面上的设置、
+/// 选中的分P和当前账号决定
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DownOptions {
+    /// 视频所在的收藏夹，记录在清单里
+    pub fid: String,
+    pub path: String,
+    pub force: bool,
+    /// dash音频流的id，None为最好的音质
+    pub quality: Option<i64>,
+    /// 文件扩展名，aac和m4a直接保存，其它格式交给ffmpeg转换
+    pub format: String,
+    /// 同时下载的视频数
+    pub concurrency: usize,
+    /// 文件名模板，见`template::Template`
+    pub template: String,
+    /// 文件名要兼容的文件系统
+    pub naming: Strictness,
+    /// 文件名只用ASCII字符：汉字转拼音，假名转罗马字。原来的标题写在标签里
+    pub ascii: bool,
+    /// 标题、分P名和UP主名的简繁转换，文件名和标签都会转换
+    pub script: Script,
+    /// 用ffmpeg写入标题、艺术家等标签，`ascii`为true时总是写入
+    pub tags: bool,
+    /// 每个视频要下载的分P（bvid→cid），不在其中的视频下载全部分P
+    pub parts: HashMap<String, HashSet<i64>>,
+    /// 下载用的账号名，记录在清单里，没有用保存的账号时为空
+    #[serde(default)]
+    pub account: String,
+}
+
+impl DownOptions {
+    pub fn new(fid: &str, path: &str) -> DownOptions {
+        DownOptions {
+            fid: fid.to_string(),
+            path: path.to_string(),
+            force: false,
+            quality: None,
+            format: String::from("aac"),
+            concurrency: 1,
+            template: String::from(DEFAULT_TEMPLATE),
+            naming: Strictness::default(),
+            ascii: false,
+            script: Script::default(),
+            tags: false,
+            parts: HashMap::new(),
+            account: String::new(),
+        }
+    }
+}
+
+/// 一次下载中各个视频共用的东西
+struct Job<'a> {
+    cred: &'a bapi::Credential,
+    opts: DownOptions,
+    template: Template,
+    base: Vars,
+    manifest: Mutex<Manifest>,
+    events: Option<UnboundedSender<DownEvent>>,
+    control: Control,
+    summary: Mutex<Summary>,
+}
+
+impl Job<'_> {
+    /// 接收端已经关闭时忽略
+    fn emit(&self, event: DownEvent) {
+        if let Some(tx) = &self.events {
+            let _ = tx.send(event);
+        }
+    }
+
+    fn part(&self, bvid: &str, cid: i64, state: PartState) {
+        self.emit(DownEvent::Part {
+            bvid: bvid.to_string(),
+            cid,
+            state,
+        });
+    }
+
+    /// 记录一项的结果，并发出对应的状态
+    async fn finish(&self, bvid: &str, cid: i64, title: &str, outcome: Outcome) {
+        let mut summary = self.summary.lock().await;
+        let (state, list, detail, kind) = match outcome {
+            Outcome::Done(path) => (
+                PartState::Done { path: path.clone() },
+                &mut summary.succeeded,
+                path,
+                None,
+            ),
+            Outcome::Skipped(reason) => (
+                PartState::Skipped {
+                    reason: reason.clone(),
+                },
+                &mut summary.skipped,
+                reason,
+                None,
+            ),
+            Outcome::Failed(f) => {
+                eprintln!("{}", f.reason);
+                let state = match f.kind {
+                    FailKind::Cancelled => PartState::Cancelled,
+                    _ => PartState::Failed {
+                        reason: f.reason.clone(),
+                    },
+                };
+                (state, &mut summary.failed, f.reason, Some(f.kind))
+            }
+        };
+        list.push(Item {
+            bvid: bvid.to_string(),
+            cid,
+            title: title.to_string(),
+            detail,
+            kind,
+        });
+        self.part(bvid, cid, state);
+    }
+
+    /// 暂停时等到继续为止，已取消时返回false
+    async fn proceed(&self, bvid: &str, cid: i64) -> bool {
+        if self.control.is_paused(bvid) {
+            self.part(bvid, cid, PartState::Paused);
+        }
+        self.control.proceed(bvid).await
+    }
+}
+
+/// 下载列表中的所有视频，返回每一项成功、跳过或失败的结果。
+/// `events`不为None时发出`DownEvent`，返回时发送端关闭；`control`用来暂停或取消。
+///
+/// 模板有误时返回`Err`，调用者应该事先用`Template::parse`检查
+pub async fn start_download(
+    v_list: &[serde_json::Value],
+    cred: &bapi::Credential,
+    opts: DownOptions,
+    events: Option<UnboundedSender<DownEvent>>,
+    control: Control,
+) -> Result<Summary, String> {
+    eprintln!("共{}项", v_list.len());
+    let template = Template::parse(&opts.template)?;
+    let mut base = Vars::default();
+    base.set("fav_id", opts.fid.as_str());
+    // 写标签时专辑名用收藏夹名
+    let need_folder = opts.template.contains("{fav_folder") || opts.ascii || opts.tags;
+    if !opts.fid.is_empty() && need_folder {
+        match bapi::get_fav_info(&opts.fid, cred).await {
+            Ok(info) => {
+                base.set(
+                    "fav_folder",
+                    translit::convert_script(
+                        info["data"]["title"].as_str().unwrap_or_default(),
+                        opts.script,
+                    ),
+                );
+            }
+            Err(e) => eprintln!("{:?}", e),
+        }
+    }
+    let concurrency = opts.concurrency.max(1);
+    let job = Job {
+        cred,
+        manifest: Mutex::new(Manifest::load(&opts.path)),
+        opts,
+        template,
+        base,
+        events,
+        control,
+        summary: Mutex::new(Summary::default()),
+    };
+    job.emit(DownEvent::Started {
+        videos: v_list.len(),
+    });
+    // 先把所有视频放进队列，取得分P列表后再换成各个分P
+    for e in v_list {
+        let title = e["title"].as_str().unwrap_or_default().to_string();
+        job.part(filter::bvid(e), 0, PartState::Queued { title });
+    }
+    futures::stream::iter(v_list.iter().enumerate())
+        .map(|(i, e)| {
+            let job = &job;
+            async move {
+                eprintln!("第{}个视频", i + 1);
+                let ok = download_video(job, e).await;
+                job.emit(DownEvent::VideoDone {
+                    bvid: filter::bvid(e).to_string(),
+                    ok,
+                });
+                eprintln!("---------");
+                ok
+            }
+        })
+        .buffer_unordered(concurrency)
+        .collect::<Vec<bool>>()
+        .await;
+    Ok(job.summary.into_inner())
+}
+
+async fn download_video(job: &Job<'_>, e: &serde_json::Value) -> bool {
+    let (cred, opts, manifest) = (job.cred, &job.opts, &job.manifest);
+    let bvid = e["bvid"].as_str().unwrap_or_default();
+    let title = filter::title(e);
+    // 已失效的视频取不到分P，不算失败
+    if filter::is_invalid(e) {
+        eprintln!("{}已失效（被删除或下架），跳过", bvid);
+        let outcome = Outcome::Skipped(String::from("已失效"));
+        job.finish(bvid, 0, title, outcome).await;
+        return true;
+    }
+    if !job.proceed(bvid, 0).await {
+        let outcome = Outcome::Failed(Failure::new(FailKind::Cancelled, "已取消"));
+        job.finish(bvid, 0, title, outcome).await;
+        return false;
+    }
+    let video_inf: bapi::VideoInf = bapi::VideoInf {
+        author: translit::convert_script(
+            e["upper"]["name"].as_str().unwrap_or_default(),
+            opts.script,
+        ),
+        name: translit::convert_script(e["title"].as_str().unwrap_or_default(), opts.script),
+    };
+    job.part(bvid, 0, PartState::Resolving);
+    let ps = match bapi::get_ps(bvid, cred).await {
+        Ok(ps) => ps,
+        Err(e) => {
+            let mut f = Failure::from(e);
+            f.reason = format!("分P获取失败：{}", f.reason);
+            job.finish(bvid, 0, title, Outcome::Failed(f)).await;
+            return false;
+        }
+    };
+    let ps = match ps["data"].as_array() {
+        Some(ps) => ps,
+        None => {
+            let outcome = Outcome::Failed(Failure::new(FailKind::Api, "没有分P"));
+            job.finish(bvid, 0, title, outcome).await;
+            return false;
+        }
+    };
+    let selected = |p: &serde_json::Value| {
+        let cid = p["cid"].as_i64().unwrap_or_default();
+        opts.parts
+            .get(bvid)
+            .map_or(true, |parts| parts.contains(&cid))
+    };
+    let part_title = |p: &serde_json::Value| {
+        if ps.len() > 1 {
+            let part = p["part"].as_str().unwrap_or_default();
+            format!(
+                "{} - {}",
+                video_inf.name,
+                translit::convert_script(part, opts.script)
+            )
+        } else {
+            video_inf.name.clone()
+        }
+    };
+    for p in ps.iter().filter(|p| selected(p)) {
+        let cid = p["cid"].as_i64().unwrap_or_default();
+        let title = part_title(p);
+        job.part(bvid, cid, PartState::Queued { title });
+    }
+    let mut vars = job.base.clone();
+    vars.set("bvid", bvid)
+        .set("title", video_inf.name.as_str())
+        .set("upper", video_inf.author.as_str())
+        .set(
+            "upper_mid",
+            e["upper"]["mid"].as_i64().unwrap_or_default().to_string(),
+        )
+        .set("part_count", ps.len().to_string())
+        .set_time(
+            "pubdate",
+            e["pubtime"]
+                .as_i64()
+                .or_else(|| e["pubdate"].as_i64())
+                .unwrap_or_default(),
+        )
+        .set_time("fav_time", e["fav_time"].as_i64().unwrap_or_default());
+    let mut ok = true;
+    for (i, p) in ps.iter().enumerate() {
+        eprintln!("第{}P", i + 1);
+        let cid = p["cid"].as_i64().unwrap_or_default();
+        if !selected(p) {
+            eprintln!("未选择，跳过");
+            continue;
+        }
+        let title = part_title(p);
+        if !opts.force && manifest.lock().await.is_intact(bvid, cid) {
+            eprintln!("已下载，跳过");
+            let outcome = Outcome::Skipped(String::from("已下载"));
+            job.finish(bvid, cid, &title, outcome).await;
+            continue;
+        }
+        let p_name = translit::convert_script(p["part"].as_str().unwrap_or_default(), opts.script);
+        vars.set("cid", cid.to_string())
+            .set("part_name", p_name.as_str())
+            .set("part", if ps.len() > 1 { p_name.as_str() } else { "" })
+            .set("part_index", (i + 1).to_string());
+        let res = download_part(job, bvid, cid, &mut vars).await;
+        match res {
+            Ok((rel_path, size, quality)) => {
+                let outcome = Outcome::Done(rel_path.clone());
+                job.finish(bvid, cid, &title, outcome).await;
+                let mut manifest = manifest.lock().await;
+                manifest.insert(manifest::Entry {
+                    bvid: bvid.to_string(),
+                    cid,
+                    path: rel_path,
+                    size,
+                    quality,
+                    timestamp: manifest::now(),
+                    title: video_inf.name.clone(),
+                    part: p_name.clone(),
+                    author: video_inf.author.clone(),
+                    fav_id: opts.fid.clone(),
+                    account: opts.account.clone(),
+                });
+                if let Err(e) = manifest.save() {
+                    eprintln!("清单写入失败：{}", e);
+                }
+            }
+            Err(f) => {
+                ok = false;
+                job.finish(bvid, cid, &title, Outcome::Failed(f)).await;
+            }
+        }
+    }
+    ok
+}
+
+/// 下载一个分P，返回相对路径、文件大小和音质
+async fn download_part(
+    job: &Job<'_>,
+    bvid: &str,
+    cid: i64,
+    vars: &mut Vars,
+) -> Result<(String, u64, i64), Failure> {
+    let (cred, opts, manifest) = (job.cred, &job.opts, &job.manifest);
+    let cancelled = || Failure::new(FailKind::Cancelled, "已取消");
+    if !job.proceed(bvid, cid).await {
+        return Err(cancelled());
+    }
+    let audio = resolve(job, bvid, cid).await?;
+    let quality = audio["id"].as_i64().unwrap_or_default();
+    vars.set("quality", bapi::quality_name(quality));
+    let mut rendered = job
+        .template
+        .render(vars, |v| sanitize::clean_value(v, opts.naming));
+    if opts.ascii {
+        rendered = translit::to_ascii(&rendered);
+    }
+    let mut file_name = sanitize::sanitize_path(&rendered, &opts.format, opts.naming);
+    if file_name.is_empty() {
+        file_name = format!("{}-{}", bvid, cid);
+    }
+    // 不同分P渲染出同一个文件名时加上后缀，而不是互相覆盖
+    let rel_path = manifest.lock().await.claim_path(
+        bvid,
+        cid,
+        &file_name,
+        &opts.format,
+        opts.naming.ignore_case(),
+    );
+    eprintln!("{}", rel_path);
+    let full_path = Path::new(&opts.path).join(&rel_path);
+    if let Some(dir) = full_path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| Failure::new(FailKind::File, format!("目录创建失败：{}", e)))?;
+    }
+    let full_path = full_path.to_string_lossy().to_string();
+    let tags = if opts.tags || opts.ascii {
+        Some(tags(vars))
+    } else {
+        None
+    };
+    let down_path = if needs_transcode(&opts.format) || tags.is_some() {
+        format!("{}.m4a.tmp", full_path)
+    } else {
+        full_path.clone()
+    };
+    let file_error = |e| Failure::new(FailKind::File, format!("文件写入失败：{}", e));
+    // 上次转换失败时留下的音频已经下载完整，重试时直接转换
+    if down_path == full_path || !Path::new(&down_path).is_file() {
+        let mut music_url = audio["baseUrl"].as_str().unwrap_or_default().to_string();
+        // 下载完成前文件名带.part，暂停时留着续传，取消时删除
+        let part_path = format!("{}.part", down_path);
+        let progress = |bytes, total| job.part(bvid, cid, PartState::Downloading { bytes, total });
+        loop {
+            let stop = job.control.interrupted(bvid);
+            match bapi::download_music(&part_path, &music_url, cred, progress, stop).await {
+                Ok(_) => break,
+                Err(e) if e.is_interrupted() => {
+                    if !job.proceed(bvid, cid).await {
+                        bapi::remove_partial(&part_path);
+                        return Err(cancelled());
+                    }
+                    // 暂停久了地址可能过期，重新获取
+                    let audio = resolve(job, bvid, cid).await?;
+                    music_url = audio["baseUrl"].as_str().unwrap_or_default().to_string();
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        std::fs::rename(&part_path, &down_path).map_err(file_error)?;
+    }
+    let size = if down_path == full_path {
+        std::fs::metadata(&full_path)
+            .map(|m| m.len())
+            .map_err(file_error)?
+    } else {
+        job.part(bvid, cid, PartState::PostProcessing);
+        postprocess(&down_path, &full_path, &opts.format, tags.as_deref())
+            .await
+            .map_err(|e| Failure::new(FailKind::Ffmpeg, e))?
+    };
+    Ok((rel_path, size, quality))
+}
+
+/// 获取分P的音频流，按`opts.quality`选择音质
+async fn resolve(job: &Job<'_>, bvid: &str, cid: i64) -> Result<serde_json::Value, Failure> {
+    job.part(bvid, cid, PartState::Resolving);
+    let u = bapi::get_url(bvid, &cid.to_string(), job.cred).await?;
+    u["data"]["dash"]["audio"]
+        .as_array()
+        .and_then(|u| bapi::pick_audio(u, job.opts.quality))
+        .cloned()
+        .ok_or_else(|| Failure::new(FailKind::Api, "没有音频流"))
+}
+
+fn needs_transcode(format: &str) -> bool {
+    format != "aac" && format != "m4a"
+}
+
+/// 标签：标题（多P时加上分P名）、艺术家为UP主、专辑为收藏夹名、注释为BV号
+fn tags(vars: &Vars) -> Vec<(&'static str, String)> {
+    let mut title = vars.value("title").to_string();
+    if !vars.value("part").is_empty() {
+        title = format!("{} - {}", title, vars.value("part"));
+    }
+    vec![
+        ("title", title),
+        ("artist", vars.value("upper").to_string()),
+        ("album", vars.value("fav_folder").to_string()),
+        ("comment", vars.value("bvid").to_string()),
+    ]
+}
+
+/// 用ffmpeg把下载的音频转换成目标格式并写入标签，返回处理后的文件大小。
+/// 不需要转换格式时只复制音频流。转换成功后才删除下载的音频，失败时留着给重试用
+async fn postprocess(
+    src: &str,
+    dst: &str,
+    format: &str,
+    tags: Option<&[(&str, String)]>,
+) -> Result<u64, String> {
+    let mut cmd = tokio::process::Command::new("ffmpeg");
+    cmd.args(["-y", "-loglevel", "error", "-i", src, "-vn"]);
+    if !needs_transcode(format) {
+        cmd.args(["-c", "copy"]);
+    }
+    for (k, v) in tags.unwrap_or_default() {
+        if !v.is_empty() {
+            cmd.arg("-metadata").arg(format!("{}={}", k, v));
+        }
+    }
+    // 裸的aac文件只能把标签写成ID3v2
+    if format == "aac" && tags.is_some() {
+        cmd.args(["-write_id3v2", "1"]);
+    }
+    match cmd.arg(dst).status().await {
+        Ok(s) if s.success() => {
+            let _ = std::fs::remove_file(src);
+            std::fs::metadata(dst)
+                .map(|m| m.len())
+                .map_err(|e| format!("文件读取失败：{}", e))
+        }
+        Ok(s) => {
+            // 转换了一半的文件会让重试时换一个文件名
+            let _ = std::fs::remove_file(dst);
+            Err(format!("ffmpeg转换失败：{}", s))
+        }
+        Err(e) => Err(format!("无法运行ffmpeg：{}", e)),
+    }
+}
@@ -0,0 +1,512 @@
+use crate::template;
+
+/// 收藏夹列表中一项的标题
+pub fn title(v: &serde_json::Value) -> &str {
+    v["title"].as_str().unwrap_or_default()
+}
+
+pub fn upper(v: &serde_json::Value) -> &str {
+    v["upper"]["name"].as_str().unwrap_or_default()
+}
+
+pub fn bvid(v: &serde_json::Value) -> &str {
+    v["bvid"].as_str().unwrap_or_default()
+}
+
+/// 所有分P的总时长（秒）
+pub fn duration(v: &serde_json::Value) -> i64 {
+    v["duration"].as_i64().unwrap_or_default()
+}
+
+/// 分P数
+pub fn parts(v: &serde_json::Value) -> i64 {
+    v["page"].as_i64().unwrap_or(1)
+}
+
+/// 发布时间，收藏夹列表中是`pubtime`
+pub fn pubdate(v: &serde_json::Value) -> i64 {
+    v["pubtime"]
+        .as_i64()
+        .or_else(|| v["pubdate"].as_i64())
+        .unwrap_or_default()
+}
+
+pub fn fav_time(v: &serde_json::Value) -> i64 {
+    v["fav_time"].as_i64().unwrap_or_default()
+}
+
+pub fn plays(v: &serde_json::Value) -> i64 {
+    v["cnt_info"]["play"].as_i64().unwrap_or_default()
+}
+
+/// 已失效（被删除或下架）的视频，收藏夹里显示为“已失效视频”
+pub fn is_invalid(v: &serde_json::Value) -> bool {
+    v["attr"].as_i64().unwrap_or_default() != 0 || title(v) == "已失效视频"
+}
+
+/// 闭区间，没有的一端不限制
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Range {
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+}
+
+impl Range {
+    pub fn contains(&self, v: i64) -> bool {
+        self.min.map_or(true, |m| v >= m) && self.max.map_or(true, |m| v <= m)
+    }
+
+    /// 解析`a~b`、`a~`、`~b`或`a`，`value`返回一个值对应的开始和结束
+    fn parse(
+        s: &str,
+        value: impl Fn(&str) -> Option<(i64, i64)>,
+        what: &str,
+    ) -> Result<Range, String> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Ok(Range::default());
+        }
+        let (a, b) = match s.find('~') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => (s, s),
+        };
+        let bound = |v: &str| -> Result<Option<(i64, i64)>, String> {
+            if v.trim().is_empty() {
+                return Ok(None);
+            }
+            value(v.trim())
+                .map(Some)
+                .ok_or_else(|| format!("无法识别的{}：{}", what, v.trim()))
+        };
+        Ok(Range {
+            min: bound(a)?.map(|v| v.0),
+            max: bound(b)?.map(|v| v.1),
+        })
+    }
+
+    /// 时长，单位为分钟，也可以写成`3:30`
+    pub fn parse_minutes(s: &str) -> Result<Range, String> {
+        Range::parse(s, |v| parse_minutes(v).map(|m| (m, m)), "时长")
+    }
+
+    pub fn parse_count(s: &str) -> Result<Range, String> {
+        Range::parse(s, |v| v.parse().ok().map(|n| (n, n)), "数量")
+    }
+
+    /// 日期，见`template::parse_date`，`2020~2021`包括2021年全年
+    pub fn parse_date(s: &str) -> Result<Range, String> {
+        Range::parse(s, template::parse_date, "日期")
+    }
+}
+
+/// 分钟数（可以有小数）或`分:秒`，返回秒数
+pub fn parse_minutes(s: &str) -> Option<i64> {
+    match s.find(':') {
+        Some(i) => {
+            let m: i64 = s[..i].trim().parse().ok()?;
+            let sec: i64 = s[i + 1..].trim().parse().ok()?;
+            Some(m * 60 + sec)
+        }
+        None => s
+            .parse::<f64>()
+            .ok()
+            .filter(|m| m.is_finite())
+            .map(|m| (m * 60.).round() as i64),
+    }
+}
+
+/// 列表的筛选条件，空的条件不筛选
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    /// 在标题和UP主名中查找，不区分大小写
+    pub query: String,
+    /// 时长（秒）
+    pub duration: Range,
+    pub parts: Range,
+    pub pubdate: Range,
+    pub fav_time: Range,
+}
+
+impl Filter {
+    pub fn matches(&self, v: &serde_json::Value) -> bool {
+        let query = self.query.trim().to_lowercase();
+        (query.is_empty()
+            || title(v).to_lowercase().contains(&query)
+            || upper(v).to_lowercase().contains(&query))
+            && self.duration.contains(duration(v))
+            && self.parts.contains(parts(v))
+            && self.pubdate.contains(pubdate(v))
+            && self.fav_time.contains(fav_time(v))
+    }
+}
+
+/// 列表的排序方式
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortKey {
+    /// 收藏夹中的顺序，即最近收藏的在前
+    #[default]
+    Fav,
+    Title,
+    Upper,
+    Duration,
+    Parts,
+    Pubdate,
+    FavTime,
+}
+
+impl SortKey {
+    pub const ALL: [SortKey; 7] = [
+        SortKey::Fav,
+        SortKey::Title,
+        SortKey::Upper,
+        SortKey::Duration,
+        SortKey::Parts,
+        SortKey::Pubdate,
+        SortKey::FavTime,
+    ];
+}
+
+impl std::fmt::Display for SortKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SortKey::Fav => "收藏顺序",
+            SortKey::Title => "标题",
+            SortKey::Upper => "UP主",
+            SortKey::Duration => "时长",
+            SortKey::Parts => "分P数",
+            SortKey::Pubdate => "发布时间",
+            SortKey::FavTime => "收藏时间",
+        })
+    }
+}
+
+/// 筛选并排序，返回要显示的项在`list`中的下标
+pub fn apply(
+    list: &[serde_json::Value],
+    filter: &Filter,
+    sort: SortKey,
+    descending: bool,
+) -> Vec<usize> {
+    let mut shown: Vec<usize> = (0..list.len())
+        .filter(|i| filter.matches(&list[*i]))
+        .collect();
+    // 稳定排序，相同的项保持收藏夹中的顺序
+    match sort {
+        SortKey::Fav => {}
+        SortKey::Title => shown.sort_by(|a, b| title(&list[*a]).cmp(title(&list[*b]))),
+        SortKey::Upper => shown.sort_by(|a, b| upper(&list[*a]).cmp(upper(&list[*b]))),
+        SortKey::Duration => shown.sort_by_key(|i| duration(&list[*i])),
+        SortKey::Parts => shown.sort_by_key(|i| parts(&list[*i])),
+        SortKey::Pubdate => shown.sort_by_key(|i| pubdate(&list[*i])),
+        SortKey::FavTime => shown.sort_by_key(|i| fav_time(&list[*i])),
+    }
+    if descending {
+        shown.reverse();
+    }
+    shown
+}
+
+/// 选择表达式中可以比较的字段
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Title,
+    Upper,
+    Bvid,
+    Duration,
+    Parts,
+    Pubdate,
+    FavTime,
+}
+
+impl Field {
+    fn parse(s: &str) -> Option<Field> {
+        Some(match s.to_lowercase().as_str() {
+            "title" | "标题" => Field::Title,
+            "upper" | "up" | "up主" => Field::Upper,
+            "bvid" => Field::Bvid,
+            "duration" | "时长" => Field::Duration,
+            "parts" | "page" | "分p数" => Field::Parts,
+            "pubdate" | "发布时间" => Field::Pubdate,
+            "fav_time" | "收藏时间" => Field::FavTime,
+            _ => return None,
+        })
+    }
+
+    fn is_text(&self) -> bool {
+        matches!(self, Field::Title | Field::Upper | Field::Bvid)
+    }
+
+    fn text<'a>(&self, v: &'a serde_json::Value) -> &'a str {
+        match self {
+            Field::Title => title(v),
+            Field::Upper => upper(v),
+            _ => v["bvid"].as_str().unwrap_or_default(),
+        }
+    }
+
+    fn number(&self, v: &serde_json::Value) -> i64 {
+        match self {
+            Field::Duration => duration(v),
+            Field::Parts => parts(v),
+            Field::Pubdate => pubdate(v),
+            _ => fav_time(v),
+        }
+    }
+
+    /// 数值字段的值对应的区间，日期是一整段时间
+    fn parse_value(&self, s: &str) -> Option<(i64, i64)> {
+        match self {
+            Field::Duration => parse_duration(s).map(|d| (d, d)),
+            Field::Parts => s.parse().ok().map(|n| (n, n)),
+            Field::Pubdate | Field::FavTime => template::parse_date(s),
+            _ => None,
+        }
+    }
+}
+
+/// `10min`、`90s`、`1h`、`3:30`，没有单位时为分钟
+fn parse_duration(s: &str) -> Option<i64> {
+    let s = s.to_lowercase();
+    for (unit, secs) in [("min", 60.), ("m", 60.), ("s", 1.), ("h", 3600.)] {
+        if let Some(n) = s.strip_suffix(unit) {
+            return n
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|n| n.is_finite())
+                .map(|n| (n * secs).round() as i64);
+        }
+    }
+    parse_minutes(&s)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    /// 包含，只用于文字
+    Contains,
+}
+
+const OPS: [(&str, Op); 7] = [
+    ("<=", Op::Le),
+    (">=", Op::Ge),
+    ("!=", Op::Ne),
+    ("<", Op::Lt),
+    (">", Op::Gt),
+    ("=", Op::Eq),
+    ("~", Op::Contains),
+];
+
+#[derive(Clone, Debug)]
+enum Cond {
+    /// 列表中的下标，闭区间
+    Index(usize, Option<usize>),
+    Text(Field, Op, String),
+    Number(Field, Op, (i64, i64)),
+}
+
+impl Cond {
+    fn parse(s: &str) -> Result<Cond, String> {
+        let s = s.trim();
+        if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit() || c == '-') {
+            let index = |n: &str| n.parse::<usize>().map_err(|_| format!("无法识别：{}", s));
+            return match s.find('-') {
+                None => index(s).map(|n| Cond::Index(n, Some(n))),
+                Some(i) => {
+                    let (a, b) = (&s[..i], &s[i + 1..]);
+                    let a = if a.is_empty() { 0 } else { index(a)? };
+                    let b = if b.is_empty() { None } else { Some(index(b)?) };
+                    if b.is_some_and(|b| b < a) {
+                        return Err(format!("区间的开始大于结束：{}", s));
+                    }
+                    Ok(Cond::Index(a, b))
+                }
+            };
+        }
+        // 取最靠前的运算符，同一位置先匹配两个字符的
+        let (pos, op_str, op) = OPS
+            .iter()
+            .filter_map(|(o, op)| s.find(o).map(|p| (p, *o, *op)))
+            .min_by_key(|(p, o, _)| (*p, std::cmp::Reverse(o.len())))
+            .ok_or_else(|| format!("无法识别：{}", s))?;
+        let name = s[..pos].trim();
+        let value = s[pos + op_str.len()..].trim().trim_matches('"');
+        let field = Field::parse(name).ok_or_else(|| format!("未知的字段：{}", name))?;
+        if field.is_text() {
+            return match op {
+                Op::Eq | Op::Ne | Op::Contains => Ok(Cond::Text(field, op, value.to_lowercase())),
+                _ => Err(format!("{}只能用=、!=或~比较", name)),
+            };
+        }
+        if op == Op::Contains {
+            return Err(format!("{}不能用~比较", name));
+        }
+        let range = field
+            .parse_value(value)
+            .ok_or_else(|| format!("无法识别的值：{}", value))?;
+        Ok(Cond::Number(field, op, range))
+    }
+
+    fn matches(&self, index: usize, v: &serde_json::Value) -> bool {
+        match self {
+            Cond::Index(a, b) => index >= *a && b.map_or(true, |b| index <= b),
+            Cond::Text(field, op, value) => {
+                let text = field.text(v).to_lowercase();
+                match op {
+                    Op::Eq => text == *value,
+                    Op::Ne => text != *value,
+                    _ => text.contains(value.as_str()),
+                }
+            }
+            Cond::Number(field, op, (lo, hi)) => {
+                let n = field.number(v);
+                match op {
+                    Op::Lt => n < *lo,
+                    Op::Le => n <= *hi,
+                    Op::Gt => n > *hi,
+                    Op::Ge => n >= *lo,
+                    Op::Eq => n >= *lo && n <= *hi,
+                    _ => n < *lo || n > *hi,
+                }
+            }
+        }
+    }
+}
+
+/// 选择表达式，如`1-20, 35, 50-`或`duration < 10min & upper = xxx`。
+///
+/// 逗号分隔的各项取并集，一项中用`&`连接的条件要同时满足。
+/// 数字和`a-b`、`a-`是列表中的下标（和列表中显示的一样从0开始）；
+/// 条件的字段有title、upper、bvid（可用`= != ~`，`~`为包含，不区分大小写）
+/// 和duration、parts、pubdate、fav_time（可用`< <= > >= = !=`）。
+/// 时长可以写`10min`、`90s`、`3:30`，日期写`2020`、`2020-05`或`2020-05-01`
+#[derive(Clone, Debug)]
+pub struct Expr {
+    terms: Vec<Vec<Cond>>,
+}
+
+impl Expr {
+    pub fn parse(s: &str) -> Result<Expr, String> {
+        let terms = s
+            .split([',', '，'])
+            .filter(|t| !t.trim().is_empty())
+            .map(|t| t.split('&').map(Cond::parse).collect())
+            .collect::<Result<Vec<Vec<Cond>>, String>>()?;
+        if terms.is_empty() {
+            return Err(String::from("选择表达式为空"));
+        }
+        Ok(Expr { terms })
+    }
+
+    /// `index`为这一项在收藏夹列表中的下标
+    pub fn matches(&self, index: usize, v: &serde_json::Value) -> bool {
+        self.terms
+            .iter()
+            .any(|t| t.iter().all(|c| c.matches(index, v)))
+    }
+
+    /// 列表中符合条件的项
+    pub fn select(&self, list: &[serde_json::Value]) -> Vec<serde_json::Value> {
+        list.iter()
+            .enumerate()
+            .filter(|(i, v)| self.matches(*i, v))
+            .map(|(_, v)| v.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn list() -> Vec<serde_json::Value> {
+        (0..7)
+            .map(|i| {
+                json!({
+                    "title": format!("Song {}", i),
+                    "upper": { "name": if i % 2 == 0 { "Alice" } else { "Bob" } },
+                    "bvid": format!("BV{}", i),
+                    "duration": i * 60 + 30,
+                    "page": i + 1,
+                    // 2020-01-01 + i*100天
+                    "pubtime": 1577808000 + i * 100 * 86400,
+                    "fav_time": 1609430400 + i,
+                })
+            })
+            .collect()
+    }
+
+    fn selected(expr: &str) -> Vec<String> {
+        Expr::parse(expr)
+            .unwrap()
+            .select(&list())
+            .iter()
+            .map(|v| bvid(v).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn index_ranges() {
+        assert_eq!(selected("0-1, 3, 5-"), ["BV0", "BV1", "BV3", "BV5", "BV6"]);
+        assert_eq!(selected("-2"), ["BV0", "BV1", "BV2"]);
+        assert_eq!(selected("50-"), Vec::<String>::new());
+        assert_eq!(selected("6-50，0"), ["BV0", "BV6"]);
+    }
+
+    #[test]
+    fn conditions() {
+        assert_eq!(selected("duration < 2min"), ["BV0", "BV1"]);
+        assert_eq!(selected("duration >= 5:30"), ["BV5", "BV6"]);
+        assert_eq!(selected("upper = alice & parts > 4"), ["BV4", "BV6"]);
+        assert_eq!(selected("title ~ \"song 3\", bvid = BV6"), ["BV3", "BV6"]);
+        // 日期是一整段时间：2020年内发布的是前4个
+        assert_eq!(selected("pubdate = 2020"), ["BV0", "BV1", "BV2", "BV3"]);
+        assert_eq!(selected("pubdate > 2020"), ["BV4", "BV5", "BV6"]);
+        assert_eq!(selected("0-3 & upper != Alice"), ["BV1", "BV3"]);
+    }
+
+    #[test]
+    fn bad_expressions() {
+        for e in [
+            "",
+            " , ",
+            "1-2-3",
+            "5-3",
+            "abc",
+            "foo = x",
+            "title < x",
+            "duration ~ 3",
+            "duration < abc",
+            "pubdate = 2021-02-30",
+            "pubdate = 9999999999999999",
+        ] {
+            assert!(Expr::parse(e).is_err(), "{:?}", e);
+        }
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("10min"), Some(600));
+        assert_eq!(parse_duration("1.5h"), Some(5400));
+        assert_eq!(parse_duration("90 s"), Some(90));
+        assert_eq!(parse_duration("3:30"), Some(210));
+        assert_eq!(parse_duration("2"), Some(120));
+        assert_eq!(parse_duration("infmin"), None);
+    }
+
+    #[test]
+    fn ranges() {
+        let r = Range::parse_minutes("1~3:30").unwrap();
+        assert_eq!((r.min, r.max), (Some(60), Some(210)));
+        let r = Range::parse_date("2020~2021").unwrap();
+        assert!(r.contains(1640966399) && !r.contains(1640966400));
+        assert_eq!(Range::parse_count(" ").unwrap(), Range::default());
+        assert!(Range::parse_count("x~").is_err());
+    }
+}
@@ -1,3 +1,19 @@
+pub mod account;
+pub mod control;
+pub mod cookies;
+pub mod cover;
+pub mod dirs;
+pub mod download;
+pub mod filter;
+pub mod manifest;
+pub mod queue;
+pub mod sanitize;
+pub mod selection;
+pub mod template;
+pub mod translit;
+pub mod vault;
+pub mod watch;
+
 pub mod bapi {
     use std::io::Write;
 
@@ -34,21 +50,127 @@ pub mod bapi {
         BiliError(BError),
     }
 
+    impl BError {
+        pub fn code(&self) -> i64 {
+            self.code
+        }
+    }
+
     impl std::error::Error for BError {}
 
+    impl std::fmt::Display for MyError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                MyError::ReqError(e) => write!(f, "{}", e),
+                MyError::BiliError(e) => write!(f, "{}", e),
+            }
+        }
+    }
+
+    impl MyError {
+        /// 账号未登录或SESSDATA已失效
+        pub fn is_not_login(&self) -> bool {
+            matches!(self, MyError::BiliError(e) if e.code == -101)
+        }
+
+        /// 下载被`download_music`的`stop`打断
+        pub fn is_interrupted(&self) -> bool {
+            matches!(self, MyError::BiliError(e) if e.code == -3)
+        }
+    }
+
     impl std::convert::From<reqwest::Error> for MyError {
         fn from(r: reqwest::Error) -> MyError {
             MyError::ReqError(r)
         }
     }
 
-    fn gen_cookie(sessdata: &str) -> String {
-        format!("sessdata={}", urlencoding::encode(sessdata))
+    /// 请求接口时带的Cookie，一个账号的登录凭据
+    #[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+    pub struct Credential {
+        pub sessdata: String,
+        #[serde(default)]
+        pub bili_jct: String,
+        #[serde(default)]
+        pub dede_user_id: String,
+        #[serde(default)]
+        pub buvid3: String,
+        /// SESSDATA过期的时间（Unix时间戳，秒），不知道时为0
+        #[serde(default)]
+        pub expires: i64,
+        /// 扫码登录时得到，用来在Cookie过期前刷新，见`refresh_credential`
+        #[serde(default)]
+        pub refresh_token: String,
+    }
+
+    impl Credential {
+        /// 只有SESSDATA，大部分接口已经够用
+        pub fn from_sessdata(sessdata: &str) -> Credential {
+            Credential {
+                sessdata: sessdata.to_string(),
+                ..Default::default()
+            }
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.sessdata.is_empty()
+        }
+
+        /// SESSDATA过期的时间。没有记录时从SESSDATA本身取，
+        /// 它的格式是`<值>,<过期时间>,<校验>`，逗号可能被编码成`%2C`
+        pub fn expires_at(&self) -> Option<i64> {
+            if self.expires > 0 {
+                return Some(self.expires);
+            }
+            let decoded = self.sessdata.replace("%2C", ",").replace("%2c", ",");
+            decoded
+                .split(',')
+                .nth(1)
+                .and_then(|t| t.parse().ok())
+                .filter(|t| *t > 0)
+        }
+    }
+
+    impl From<LoginInfo> for Credential {
+        fn from(info: LoginInfo) -> Credential {
+            Credential {
+                sessdata: info.sessdata,
+                bili_jct: info.bili_jct,
+                dede_user_id: info.dede_user_id,
+                buvid3: String::new(),
+                expires: info.expires,
+                refresh_token: info.refresh_token,
+            }
+        }
+    }
+
+    fn gen_cookie(cred: &Credential) -> String {
+        let mut cookie = format!("SESSDATA={}", urlencoding::encode(&cred.sessdata));
+        for (name, value) in [
+            ("bili_jct", &cred.bili_jct),
+            ("DedeUserID", &cred.dede_user_id),
+            ("buvid3", &cred.buvid3),
+        ] {
+            if !value.is_empty() {
+                cookie.push_str(&format!("; {}={}", name, urlencoding::encode(value)));
+            }
+        }
+        cookie
     }
 
     pub async fn get_fav_list(
         fid: &str,
-        sessdata: &str,
+        cred: &Credential,
+    ) -> Result<Vec<serde_json::Value>, MyError> {
+        get_fav_list_since(fid, cred, 0).await
+    }
+
+    /// 只获取收藏时间晚于`since`的视频。收藏夹默认按收藏时间从新到旧排列，
+    /// 遇到更早的就不再翻页
+    pub async fn get_fav_list_since(
+        fid: &str,
+        cred: &Credential,
+        since: i64,
     ) -> Result<Vec<serde_json::Value>, MyError> {
         let mut has_more = true;
         let mut list: Vec<serde_json::Value> = vec![];
@@ -59,21 +181,17 @@ pub mod bapi {
                     "https://api.bilibili.com/x/v3/fav/resource/list?media_id={}&pn={}&ps=20",
                     fid, pn
                 ))
-                .header("Cookie", gen_cookie(sessdata))
+                .header("Cookie", gen_cookie(cred))
                 .send()
                 .await?
                 .json()
                 .await?;
-            let msg = body["message"].as_str().unwrap_or_default();
-            let code = body["code"].as_i64().unwrap_or_default();
-            if code != 0 {
-                return Err(MyError::BiliError(BError {
-                    code,
-                    msg: String::from(msg),
-                }));
-            }
+            let body = check_code(body)?;
             if let Some(l) = body["data"]["medias"].as_array() {
                 for x in l {
+                    if x["fav_time"].as_i64().unwrap_or_default() <= since {
+                        return Ok(list);
+                    }
                     list.push(x.clone());
                 }
             }
@@ -83,43 +201,430 @@ pub mod bapi {
         Ok(list)
     }
 
-    pub async fn get_ps(bvid: &str, sessdata: &str) -> Result<serde_json::Value, MyError> {
+    pub async fn get_ps(bvid: &str, cred: &Credential) -> Result<serde_json::Value, MyError> {
         let body: serde_json::Value = reqwest::Client::new()
             .get(format!(
                 "https://api.bilibili.com/x/player/pagelist?bvid={}",
                 bvid
             ))
-            .header("Cookie", gen_cookie(sessdata))
+            .header("Cookie", gen_cookie(cred))
             .send()
             .await?
             .json()
             .await?;
-        let msg = body["message"].as_str().unwrap_or_default();
-        let code = body["code"].as_i64().unwrap_or_default();
-        if code != 0 {
-            return Err(MyError::BiliError(BError {
-                code,
-                msg: String::from(msg),
-            }));
-        }
-        Ok(body)
+        check_code(body)
     }
 
     pub async fn get_url(
         bvid: &str,
         cid: &str,
-        sessdata: &str,
+        cred: &Credential,
     ) -> Result<serde_json::Value, MyError> {
         let body: serde_json::Value = reqwest::Client::new()
             .get(format!(
                 "https://api.bilibili.com/x/player/playurl?bvid={}&cid={}&fnval=16",
                 bvid, cid
             ))
-            .header("Cookie", gen_cookie(sessdata))
+            .header("Cookie", gen_cookie(cred))
+            .send()
+            .await?
+            .json()
+            .await?;
+        check_code(body)
+    }
+
+    /// 当前登录的账号，未登录时`data.isLogin`为false
+    pub async fn get_nav(cred: &Credential) -> Result<serde_json::Value, MyError> {
+        let body: serde_json::Value = reqwest::Client::new()
+            .get("https://api.bilibili.com/x/web-interface/nav")
+            .header("Cookie", gen_cookie(cred))
+            .send()
+            .await?
+            .json()
+            .await?;
+        // 未登录时code为-101，但data里仍有isLogin
+        if !body["data"]["isLogin"].as_bool().unwrap_or_default() {
+            return Err(MyError::BiliError(BError {
+                code: -101,
+                msg: String::from("账号未登录"),
+            }));
+        }
+        check_code(body)
+    }
+
+    /// 用户创建的所有收藏夹
+    pub async fn get_fav_folders(
+        mid: i64,
+        cred: &Credential,
+    ) -> Result<Vec<serde_json::Value>, MyError> {
+        let body: serde_json::Value = reqwest::Client::new()
+            .get(format!(
+                "https://api.bilibili.com/x/v3/fav/folder/created/list-all?up_mid={}",
+                mid
+            ))
+            .header("Cookie", gen_cookie(cred))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let body = check_code(body)?;
+        Ok(body["data"]["list"].as_array().cloned().unwrap_or_default())
+    }
+
+    /// 收藏夹的信息，`data.title`为收藏夹名
+    pub async fn get_fav_info(fid: &str, cred: &Credential) -> Result<serde_json::Value, MyError> {
+        let body: serde_json::Value = reqwest::Client::new()
+            .get(format!(
+                "https://api.bilibili.com/x/v3/fav/folder/info?media_id={}",
+                fid
+            ))
+            .header("Cookie", gen_cookie(cred))
+            .send()
+            .await?
+            .json()
+            .await?;
+        check_code(body)
+    }
+
+    /// 单个视频的信息，用于直接下载视频链接
+    pub async fn get_view(bvid: &str, cred: &Credential) -> Result<serde_json::Value, MyError> {
+        let body: serde_json::Value = reqwest::Client::new()
+            .get(format!(
+                "https://api.bilibili.com/x/web-interface/view?bvid={}",
+                bvid
+            ))
+            .header("Cookie", gen_cookie(cred))
+            .send()
+            .await?
+            .json()
+            .await?;
+        check_code(body)
+    }
+
+    /// 音质名称和dash音频流的id
+    pub const QUALITIES: [(&str, i64); 3] = [("64k", 30216), ("132k", 30232), ("192k", 30280)];
+
+    pub fn quality_id(name: &str) -> Option<i64> {
+        QUALITIES
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, id)| *id)
+    }
+
+    pub fn quality_name(id: i64) -> String {
+        match QUALITIES.iter().find(|(_, q)| *q == id) {
+            Some((n, _)) => n.to_string(),
+            None => id.to_string(),
+        }
+    }
+
+    /// 从`data.dash.audio`中选出音频流。`quality`为None时选最好的，
+    /// 否则选不高于它的最好的一个，都比它高就选最低的
+    pub fn pick_audio(
+        audio: &[serde_json::Value],
+        quality: Option<i64>,
+    ) -> Option<&serde_json::Value> {
+        let rank = |id: i64| QUALITIES.iter().position(|(_, q)| *q == id);
+        let mut sorted: Vec<&serde_json::Value> = audio.iter().collect();
+        sorted.sort_by_key(|a| a["bandwidth"].as_i64().unwrap_or_default());
+        match quality.and_then(rank) {
+            Some(max) => sorted
+                .iter()
+                .rev()
+                .find(|a| match rank(a["id"].as_i64().unwrap_or_default()) {
+                    Some(r) => r <= max,
+                    None => false,
+                })
+                .or_else(|| sorted.first())
+                .cloned(),
+            None => sorted.last().cloned(),
+        }
+    }
+
+    pub struct QrLogin {
+        /// 要编码进二维码的链接
+        pub url: String,
+        pub qrcode_key: String,
+    }
+
+    #[derive(Clone, Debug, Default)]
+    pub struct LoginInfo {
+        pub sessdata: String,
+        pub bili_jct: String,
+        pub dede_user_id: String,
+        pub refresh_token: String,
+        /// SESSDATA过期的时间（Unix时间戳，秒）
+        pub expires: i64,
+    }
+
+    pub enum QrStatus {
+        NotScanned,
+        Scanned,
+        Expired,
+        Done(LoginInfo),
+    }
+
+    pub async fn qr_login_generate() -> Result<QrLogin, MyError> {
+        let body: serde_json::Value = reqwest::Client::new()
+            .get("https://passport.bilibili.com/x/passport-login/web/qrcode/generate")
+            .send()
+            .await?
+            .json()
+            .await?;
+        let body = check_code(body)?;
+        Ok(QrLogin {
+            url: body["data"]["url"].as_str().unwrap_or_default().into(),
+            qrcode_key: body["data"]["qrcode_key"]
+                .as_str()
+                .unwrap_or_default()
+                .into(),
+        })
+    }
+
+    pub async fn qr_login_poll(qrcode_key: &str) -> Result<QrStatus, MyError> {
+        let body: serde_json::Value = reqwest::Client::new()
+            .get(format!(
+                "https://passport.bilibili.com/x/passport-login/web/qrcode/poll?qrcode_key={}",
+                qrcode_key
+            ))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let body = check_code(body)?;
+        let data = &body["data"];
+        match data["code"].as_i64().unwrap_or_default() {
+            0 => {
+                // 登录成功后跳转的链接里带着所有Cookie
+                let mut info = LoginInfo {
+                    refresh_token: data["refresh_token"].as_str().unwrap_or_default().into(),
+                    ..Default::default()
+                };
+                if let Ok(u) = reqwest::Url::parse(data["url"].as_str().unwrap_or_default()) {
+                    for (k, v) in u.query_pairs() {
+                        match k.as_ref() {
+                            "SESSDATA" => info.sessdata = v.into(),
+                            "bili_jct" => info.bili_jct = v.into(),
+                            "DedeUserID" => info.dede_user_id = v.into(),
+                            "Expires" => info.expires = v.parse().unwrap_or_default(),
+                            _ => {}
+                        }
+                    }
+                }
+                Ok(QrStatus::Done(info))
+            }
+            86090 => Ok(QrStatus::Scanned),
+            86038 => Ok(QrStatus::Expired),
+            86101 => Ok(QrStatus::NotScanned),
+            code => Err(MyError::BiliError(BError {
+                code,
+                msg: data["message"].as_str().unwrap_or_default().into(),
+            })),
+        }
+    }
+
+    /// 网页端刷新Cookie时加密correspondPath用的公钥
+    #[cfg(feature = "cookie-refresh")]
+    const REFRESH_PUBLIC_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQDLgd2OAkcGVtoE3ThUREbio0Eg
+Uc/prcajMKXvkCKFCWhJYJcLkcM2DKKcSeFpD/j6Boy538YXnR6VhcuUJOhH2x71
+nzPjfdTcqMz7djHum0qSZA0AyCBDABUqCrfNgCiJ00Ra7GmRj+YCK1NJEuewlb40
+JNrRuoEUXpabUzGB8QIDAQAB
+-----END PUBLIC KEY-----";
+
+    fn refresh_error(msg: impl Into<String>) -> MyError {
+        MyError::BiliError(BError {
+            code: -4,
+            msg: format!("Cookie刷新失败：{}", msg.into()),
+        })
+    }
+
+    /// 用RSA-OAEP(SHA256)加密`refresh_{timestamp}`，结果为十六进制
+    #[cfg(feature = "cookie-refresh")]
+    fn correspond_path(timestamp: i64) -> Result<String, MyError> {
+        use rsa::pkcs8::DecodePublicKey;
+        let key = rsa::RsaPublicKey::from_public_key_pem(REFRESH_PUBLIC_KEY)
+            .map_err(|e| refresh_error(e.to_string()))?;
+        let sealed = key
+            .encrypt(
+                &mut rsa::rand_core::OsRng,
+                rsa::Oaep::new::<sha2::Sha256>(),
+                format!("refresh_{}", timestamp).as_bytes(),
+            )
+            .map_err(|e| refresh_error(e.to_string()))?;
+        Ok(sealed.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    #[cfg(not(feature = "cookie-refresh"))]
+    fn correspond_path(_timestamp: i64) -> Result<String, MyError> {
+        Err(refresh_error("没有启用cookie-refresh feature"))
+    }
+
+    /// 检查Cookie是否需要刷新，需要时返回服务器的时间戳（毫秒）
+    pub async fn cookie_info(cred: &Credential) -> Result<Option<i64>, MyError> {
+        let body: serde_json::Value = reqwest::Client::new()
+            .get(format!(
+                "https://passport.bilibili.com/x/passport-login/web/cookie/info?csrf={}",
+                cred.bili_jct
+            ))
+            .header("Cookie", gen_cookie(cred))
             .send()
             .await?
             .json()
             .await?;
+        let body = check_code(body)?;
+        if body["data"]["refresh"].as_bool().unwrap_or_default() {
+            Ok(Some(body["data"]["timestamp"].as_i64().unwrap_or_default()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// 用refresh_token刷新Cookie，不需要刷新时返回None。
+    ///
+    /// 依次取得refresh_csrf、刷新、确认刷新；确认后旧的Cookie和refresh_token失效，
+    /// 调用者要保存返回的凭据
+    pub async fn refresh_credential(cred: &Credential) -> Result<Option<Credential>, MyError> {
+        if cred.refresh_token.is_empty() || cred.bili_jct.is_empty() {
+            return Err(refresh_error("没有refresh_token或bili_jct，请扫码登录"));
+        }
+        let timestamp = match cookie_info(cred).await? {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+        let client = reqwest::Client::new();
+        let html = client
+            .get(format!(
+                "https://www.bilibili.com/correspond/1/{}",
+                correspond_path(timestamp)?
+            ))
+            .header("Cookie", gen_cookie(cred))
+            .send()
+            .await?
+            .text()
+            .await?;
+        let refresh_csrf = html
+            .split("<div id=\"1-name\">")
+            .nth(1)
+            .and_then(|s| s.split("</div>").next())
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .ok_or_else(|| refresh_error("没有取得refresh_csrf"))?
+            .to_string();
+        let resp = client
+            .post("https://passport.bilibili.com/x/passport-login/web/cookie/refresh")
+            .header("Cookie", gen_cookie(cred))
+            .form(&[
+                ("csrf", cred.bili_jct.as_str()),
+                ("refresh_csrf", refresh_csrf.as_str()),
+                ("source", "main_web"),
+                ("refresh_token", cred.refresh_token.as_str()),
+            ])
+            .send()
+            .await?;
+        // 新的Cookie在Set-Cookie中
+        let mut new = Credential {
+            buvid3: cred.buvid3.clone(),
+            ..Default::default()
+        };
+        for header in resp.headers().get_all(reqwest::header::SET_COOKIE) {
+            let mut attrs = header.to_str().unwrap_or_default().split(';');
+            if let Some((name, value)) = attrs.next().and_then(|p| p.split_once('=')) {
+                let value = urlencoding::decode(value).unwrap_or_else(|_| value.to_string());
+                match name.trim() {
+                    "SESSDATA" => {
+                        new.sessdata = value;
+                        new.expires = cookie_expiry(attrs);
+                    }
+                    "bili_jct" => new.bili_jct = value,
+                    "DedeUserID" => new.dede_user_id = value,
+                    _ => {}
+                }
+            }
+        }
+        let body = check_code(resp.json().await?)?;
+        new.refresh_token = body["data"]["refresh_token"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        if new.is_empty() || new.refresh_token.is_empty() {
+            return Err(refresh_error("没有返回新的Cookie"));
+        }
+        let body: serde_json::Value = client
+            .post("https://passport.bilibili.com/x/passport-login/web/confirm/refresh")
+            .header("Cookie", gen_cookie(&new))
+            .form(&[
+                ("csrf", new.bili_jct.as_str()),
+                ("refresh_token", cred.refresh_token.as_str()),
+            ])
+            .send()
+            .await?
+            .json()
+            .await?;
+        check_code(body)?;
+        Ok(Some(new))
+    }
+
+    /// 从Set-Cookie的属性中取过期时间（Unix时间戳），`Max-Age`优先于`Expires`，都没有时为0
+    fn cookie_expiry<'a>(attrs: impl Iterator<Item = &'a str>) -> i64 {
+        let (mut max_age, mut expires) = (None, None);
+        for attr in attrs {
+            let (key, value) = attr.split_once('=').unwrap_or((attr, ""));
+            match key.trim().to_ascii_lowercase().as_str() {
+                "max-age" => max_age = value.trim().parse::<i64>().ok(),
+                "expires" => expires = parse_http_date(value),
+                _ => {}
+            }
+        }
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        max_age
+            .map(|age| now.saturating_add(age))
+            .or(expires)
+            .unwrap_or_default()
+    }
+
+    /// 解析Set-Cookie中`Expires`的HTTP日期，如`Wed, 21 Oct 2015 07:28:00 GMT`，
+    /// 日期中的空格也可以是`-`，返回Unix时间戳
+    fn parse_http_date(s: &str) -> Option<i64> {
+        use crate::template::{days_from_civil, days_in_month};
+        const MONTHS: [&str; 12] = [
+            "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+        ];
+        let s = s.trim();
+        let s = s.split_once(',').map_or(s, |(_, rest)| rest);
+        let parts: Vec<&str> = s.split([' ', '-']).filter(|p| !p.is_empty()).collect();
+        let (d, m, y, time) = match parts[..] {
+            [d, m, y, time, ..] => (d, m, y, time),
+            _ => return None,
+        };
+        let d = d.parse::<i64>().ok()?;
+        let m = MONTHS
+            .iter()
+            .position(|name| m.get(..3).is_some_and(|m| m.eq_ignore_ascii_case(name)))?
+            as i64
+            + 1;
+        let y = match y.parse::<i64>().ok()? {
+            y @ 0..=69 => y + 2000,
+            y @ 70..=99 => y + 1900,
+            y => y,
+        };
+        let hms = time
+            .split(':')
+            .map(|n| n.parse::<i64>().ok())
+            .collect::<Option<Vec<i64>>>()?;
+        let secs = match hms[..] {
+            [h, min, sec] if h < 24 && min < 60 && sec < 61 => h * 3600 + min * 60 + sec,
+            _ => return None,
+        };
+        if !(1..=9999).contains(&y) || !(1..=days_in_month(y, m)).contains(&d) {
+            return None;
+        }
+        Some(days_from_civil(y, m, d) * 86400 + secs)
+    }
+
+    fn check_code(body: serde_json::Value) -> Result<serde_json::Value, MyError> {
         let msg = body["message"].as_str().unwrap_or_default();
         let code = body["code"].as_i64().unwrap_or_default();
         if code != 0 {
@@ -131,33 +636,163 @@ pub mod bapi {
         Ok(body)
     }
 
-    pub async fn download_music(path: &str, url: &str, sessdata: &str) -> Result<i64, MyError> {
-        let body = reqwest::Client::new()
-            .get(url)
-            .header("Cookie", gen_cookie(sessdata))
-            .header("Referer", "https://www.bilibili.com")
-            .header("Origin", "https://www.bilibili.com")
-            .header(
-                "User-Agent",
-                " Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:90.0) Gecko/20100101 Firefox/90.0",
-            )
-            .send()
-            .await?
-            .bytes()
-            .await?;
-        let file = std::fs::File::create(path);
-        return match file {
-            Ok(mut file) => match file.write_all(&body.slice(..)) {
-                Ok(_) => Ok(0),
-                Err(e) => Err(MyError::BiliError(BError {
-                    code: -2,
-                    msg: String::from(format!("文件写入失败：{}", e)),
-                })),
-            },
-            Err(e) => Err(MyError::BiliError(BError {
-                code: -1,
-                msg: String::from(format!("文件创建失败：{}", e)),
-            })),
+    /// 续传时用来确认服务器上还是同一个文件：第一次下载时的ETag和文件大小，
+    /// 保存在下载文件旁的`.meta`文件里
+    #[derive(Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Partial {
+        etag: String,
+        length: u64,
+    }
+
+    fn partial_meta(path: &str) -> String {
+        format!("{}.meta", path)
+    }
+
+    /// 删除没下载完的文件和续传用的记录
+    pub fn remove_partial(path: &str) {
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(partial_meta(path));
+    }
+
+    /// 边下载边写入文件，每收到一块数据就用已下载的字节数和文件大小调用`progress`。
+    ///
+    /// 下载到`path`。文件已经存在时从末尾续传，服务器上的文件变了（ETag或大小不同，
+    /// 比如换了音质）或服务器不支持续传时重新下载。HTTP状态不是成功时返回错误。
+    ///
+    /// `stop`完成时中断下载，返回`is_interrupted()`的错误，已下载的部分留在文件里
+    pub async fn download_music(
+        path: &str,
+        url: &str,
+        cred: &Credential,
+        mut progress: impl FnMut(u64, Option<u64>),
+        stop: impl std::future::Future<Output = ()>,
+    ) -> Result<i64, MyError> {
+        tokio::pin!(stop);
+        let interrupted = || {
+            MyError::BiliError(BError {
+                code: -3,
+                msg: String::from("下载中断"),
+            })
+        };
+        let file_error = |code, msg: &str, e: std::io::Error| {
+            MyError::BiliError(BError {
+                code,
+                msg: format!("{}：{}", msg, e),
+            })
+        };
+        let meta = partial_meta(path);
+        // 没有记录时不知道已下载的部分是哪个文件的，只能重新下载
+        let partial: Option<Partial> = std::fs::read(&meta)
+            .ok()
+            .and_then(|s| serde_json::from_slice(&s).ok());
+        let mut offset = match partial {
+            Some(_) => std::fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+            None => 0,
+        };
+        let mut resp = loop {
+            let mut req = reqwest::Client::new()
+                .get(url)
+                .header("Cookie", gen_cookie(cred))
+                .header("Referer", "https://www.bilibili.com")
+                .header("Origin", "https://www.bilibili.com")
+                .header(
+                    "User-Agent",
+                    " Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:90.0) Gecko/20100101 Firefox/90.0",
+                );
+            if let (true, Some(p)) = (offset > 0, &partial) {
+                req = req.header("Range", format!("bytes={}-", offset));
+                // ETag不同时服务器返回整个文件
+                if !p.etag.is_empty() {
+                    req = req.header("If-Range", p.etag.as_str());
+                }
+            }
+            let resp = tokio::select! {
+                resp = req.send() => resp?,
+                _ = &mut stop => return Err(interrupted()),
+            };
+            // 文件比服务器上的还长，只能重新下载
+            if offset > 0 && resp.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+                offset = 0;
+                continue;
+            }
+            // Content-Range中的总大小和原来的不同，已下载的部分不能用
+            if offset > 0 && resp.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+                let length = resp
+                    .headers()
+                    .get(reqwest::header::CONTENT_RANGE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.rsplit('/').next())
+                    .and_then(|v| v.parse::<u64>().ok());
+                if length != partial.as_ref().map(|p| p.length) {
+                    offset = 0;
+                    continue;
+                }
+            }
+            break resp.error_for_status()?;
+        };
+        if resp.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+            offset = 0;
+        }
+        let total = resp.content_length().map(|l| l + offset);
+        if offset == 0 {
+            let partial = Partial {
+                etag: resp
+                    .headers()
+                    .get(reqwest::header::ETAG)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string(),
+                length: total.unwrap_or_default(),
+            };
+            let data = serde_json::to_vec(&partial).unwrap_or_default();
+            std::fs::write(&meta, data).map_err(|e| file_error(-1, "文件创建失败", e))?;
+        }
+        let file = if offset > 0 {
+            std::fs::OpenOptions::new().append(true).open(path)
+        } else {
+            std::fs::File::create(path)
         };
+        let mut file = file.map_err(|e| file_error(-1, "文件创建失败", e))?;
+        let mut size = offset;
+        progress(size, total);
+        loop {
+            let chunk = tokio::select! {
+                chunk = resp.chunk() => chunk?,
+                _ = &mut stop => return Err(interrupted()),
+            };
+            let chunk = match chunk {
+                Some(chunk) => chunk,
+                None => break,
+            };
+            file.write_all(&chunk)
+                .map_err(|e| file_error(-2, "文件写入失败", e))?;
+            size += chunk.len() as u64;
+            progress(size, total);
+        }
+        let _ = std::fs::remove_file(&meta);
+        Ok(size as i64)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn http_dates() {
+            assert_eq!(
+                parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT"),
+                Some(1445412480)
+            );
+            assert_eq!(parse_http_date("Thu, 01-Jan-1970 00:00:01 GMT"), Some(1));
+            assert_eq!(
+                parse_http_date("Sun, 29-Feb-24 12:00:00 GMT"),
+                Some(1709208000)
+            );
+            assert_eq!(parse_http_date("Fri, 30 Feb 2024 12:00:00 GMT"), None);
+            assert_eq!(parse_http_date("Wed, 21 Foo 2015 07:28:00 GMT"), None);
+            assert_eq!(parse_http_date("Wed, 21 Oct 2015 24:00:00 GMT"), None);
+            assert_eq!(parse_http_date("1445412480"), None);
+            assert_eq!(parse_http_date("Wed, 21 Oct 99999 07:28:00 GMT"), None);
+        }
     }
 }
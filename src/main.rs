@@ -1,11 +1,28 @@
-use bili_music_download::bapi;
+use bili_music_download::account::{self, Accounts, Profile};
+use bili_music_download::bapi::{self, Credential};
+use bili_music_download::control::Control;
+use bili_music_download::cookies::{self, ChromiumKey};
+use bili_music_download::cover;
+use bili_music_download::download::{start_download, DownEvent, DownOptions, PartState, Summary};
+use bili_music_download::filter::{self, Expr, Filter, Range, SortKey};
+use bili_music_download::manifest::{Manifest, MirrorAction};
+use bili_music_download::queue::Journal;
+use bili_music_download::sanitize::Strictness;
+use bili_music_download::selection::{Presets, Selection};
+use bili_music_download::template::{self, Template, DEFAULT_TEMPLATE};
+use bili_music_download::translit::{self, Script};
+use bili_music_download::vault::{self, Vault};
+use futures::StreamExt;
 use iced::{
-    button, scrollable, text_input, Application, Button, Checkbox, Clipboard, Column, Command,
-    Container, Element, Length, Row, Scrollable, Settings, Text, TextInput,
+    button, image, pick_list, scrollable, text_input, Application, Button, Checkbox, Clipboard,
+    Color, Column, Command, Container, Element, Image, Length, PickList, Row, Scrollable, Settings,
+    Space, Subscription, Text, TextInput,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Instant;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::sync::{Mutex, Semaphore};
 
 #[tokio::main]
 async fn main() -> iced::Result {
@@ -18,24 +35,96 @@ async fn main() -> iced::Result {
 #[derive(Clone, Debug)]
 enum Message {
     QrLoginPressed,
+    /// 是否继续上次没完成的下载队列
+    ResumeQueue(bool),
     CookieLoginPressed,
     FinishCookieLogin,
+    /// 账号的mid和昵称，Err(None)表示Cookie无效
+    GotAccount(Result<(i64, String), Option<String>>),
+    /// 用refresh_token刷新Cookie的结果，不需要刷新时是Ok(None)
+    Refreshed(Result<Option<Credential>, String>),
+    /// 登录或切换到保存的账号
+    UseAccount(String),
+    ProfileNameChanged(String),
+    PassphraseChanged(String),
+    ChromiumKeyChanged(String),
+    /// 选择cookies.txt或浏览器的Cookie数据库登录
+    ImportCookies,
+    CookieFileChosen(Option<std::path::PathBuf>),
+    ExportCookies,
+    CookieExportChosen(Option<std::path::PathBuf>),
+    /// 用口令解锁凭据库，没有凭据库时新建
+    Unlock,
+    /// 把当前的目录和下载选项保存为账号的默认设置
+    SaveDefaults,
+    /// 从下载页回到列表，保留选择
+    Back,
+    ChangeFolder,
+    Logout,
+    /// 下载完后重新获取列表，开始新的一批
+    NewBatch,
     CookieInputChanged(String),
     FavInputChanged(String),
     GetList,
-    GotList(Option<Vec<serde_json::Value>>),
+    /// 获取的收藏夹编号和列表
+    GotList(String, Option<Vec<serde_json::Value>>),
     Check(usize, CheckMessage),
+    GotParts(usize, String, Option<Vec<serde_json::Value>>),
+    GotCover(usize, String, Option<std::path::PathBuf>),
     SelectAll,
     Fanxuan,
     Clear,
     FinalStep,
     ChooseFile,
     StartDown,
-    Finish(bool),
+    Finish(Result<Summary, String>),
+    RetryFailed,
     ChangePath(String),
-    ChangeProg(f64),
+    Download(DownEvent),
+    Retry(String, i64),
+    /// None为整个队列，否则为一个视频的bvid
+    Pause(Option<String>),
+    Resume(Option<String>),
+    Cancel(Option<String>),
+    OpenLocation(String),
+    ForceChanged(bool),
+    /// 移到_removed和删除先预览，确认后才执行
+    Mirror(MirrorAction, bool),
+    MirrorCancel,
+    InvalidReport,
+    TemplateChanged(String),
+    NamingChanged(Strictness),
+    AsciiChanged(bool),
+    ScriptChanged(Script),
+    TagsChanged(bool),
+    PresetNameChanged(String),
+    SavePreset,
+    ApplyPreset(String),
+    FilterChanged(FilterField, String),
+    SortChanged(SortKey),
+    SortDescChanged(bool),
+    ExprChanged(String),
+    ApplyExpr(bool),
+    ShiftChanged(bool),
 }
 
+#[derive(Clone, Copy, Debug)]
+enum FilterField {
+    Query,
+    Duration,
+    Parts,
+    Pubdate,
+    FavTime,
+}
+
+const FILTER_FIELDS: [(FilterField, &str); 5] = [
+    (FilterField::Query, "搜索标题、UP主"),
+    (FilterField::Duration, "时长（分钟），如3~10"),
+    (FilterField::Parts, "分P数，如2~"),
+    (FilterField::Pubdate, "发布日期，如2020~2021-06"),
+    (FilterField::FavTime, "收藏日期"),
+];
+
 enum Pages {
     Login,
     CookieLogin,
@@ -45,6 +134,31 @@ enum Pages {
 
 struct App {
     page: Pages,
+    /// 登录的账号名，显示在页面顶部
+    account: Option<String>,
+    /// 保存的账号
+    accounts: Accounts,
+    /// 正在用的保存的账号，Cookie登录时没填账号名则为None
+    profile: Option<String>,
+    account_pick: pick_list::State<String>,
+    profile_input: text_input::State,
+    /// Cookie登录时保存为这个名字的账号
+    profile_name: String,
+    save_defaults_button: button::State,
+    /// 解锁后的凭据库，退出登录时重新锁上
+    vault: Option<Vault>,
+    passphrase_input: text_input::State,
+    passphrase: String,
+    unlock_button: button::State,
+    import_cookies_button: button::State,
+    export_cookies_button: button::State,
+    chromium_key_input: text_input::State,
+    /// Chromium加密Cookie的密钥或密码，见`ChromiumKey::parse`
+    chromium_key: String,
+    back_button: button::State,
+    change_folder_button: button::State,
+    logout_button: button::State,
+    new_batch_button: button::State,
     qr_login_button: button::State,
     cookie_login_button: button::State,
     select_all_button: button::State,
@@ -53,25 +167,287 @@ struct App {
     final_step_button: button::State,
     choose_file_button: button::State,
     start_download_button: button::State,
+    retry_failed_button: button::State,
+    pause_button: button::State,
+    cancel_button: button::State,
+    /// 下载队列的记录，重启后可以继续
+    journal: Journal,
+    /// 登录后继续上次的队列
+    resume_queue: bool,
+    resume_button: button::State,
+    discard_button: button::State,
     scroll: scrollable::State,
     cookie_input: text_input::State,
     fav_id_input: text_input::State,
     do_cookie_login_button: button::State,
-    cookie_value: &'static str,
+    cookie_value: String,
+    /// 和后台任务共用，换账号时直接替换
+    credential: Arc<Credential>,
     cookie_placeholder: String,
-    fav_id_value: &'static str,
+    fav_id_value: String,
     fav_id_placeholder: String,
     get_list_button: button::State,
     fav_list: Vec<serde_json::Value>,
-    down_list: &'static Vec<serde_json::Value>,
-    fav_list_v: HashMap<usize, bool>,
+    /// `fav_list`是哪个收藏夹的完整列表，没有获取过时为None
+    list_fid: Option<String>,
+    /// 要下载的视频，下载任务持有一份引用
+    down_list: Arc<Vec<serde_json::Value>>,
     fav_lists: Vec<Check>,
+    /// 限制同时下载的封面数
+    cover_limit: Arc<Semaphore>,
+    /// 筛选、排序后显示的项在`fav_list`中的下标
+    shown: Vec<usize>,
+    /// 按`FILTER_FIELDS`的顺序
+    filter_inputs: Vec<(text_input::State, String)>,
+    filter_msg: String,
+    sort_pick: pick_list::State<SortKey>,
+    sort: SortKey,
+    sort_desc: bool,
+    expr_input: text_input::State,
+    expr_value: String,
+    expr_select_button: button::State,
+    expr_deselect_button: button::State,
+    /// 按住Shift时点击的复选框会选中或取消和上一次点击之间的所有项
+    shift: bool,
+    last_check: Option<usize>,
+    /// 列表和下载共用的选择
+    selection: Selection,
+    presets: Presets,
+    preset_input: text_input::State,
+    preset_name: String,
+    preset_pick: pick_list::State<String>,
+    save_preset_button: button::State,
     msg: String,
-    path: &'static str,
-    progress: &'static Arc<Mutex<f64>>,
+    path: String,
     downloading: bool,
-    prog_percent: f64,
-    start_down_msg: String,
+    /// 下载引擎的事件，下载中时由`subscription`接收
+    events: Option<Arc<Mutex<UnboundedReceiver<DownEvent>>>>,
+    /// 每次下载换一个，让iced重新订阅
+    download_id: u64,
+    /// 暂停、取消当前的下载，每次下载换一个
+    control: Control,
+    /// 本次下载的结果，重试后合并
+    summary: Summary,
+    progress: Progress,
+    force_download: bool,
+    mirror_preview_button: button::State,
+    mirror_archive_button: button::State,
+    mirror_delete_button: button::State,
+    mirror_confirm_button: button::State,
+    mirror_cancel_button: button::State,
+    /// 预览过、等待确认的移到_removed或删除
+    mirror_pending: Option<MirrorAction>,
+    invalid_button: button::State,
+    mirror_msg: String,
+    template_input: text_input::State,
+    template_value: String,
+    naming_pick: pick_list::State<Strictness>,
+    naming: Strictness,
+    ascii: bool,
+    script_pick: pick_list::State<Script>,
+    script: Script,
+    tags: bool,
+}
+
+impl App {
+    fn filter_value(&self, field: FilterField) -> &str {
+        &self.filter_inputs[field as usize].1
+    }
+
+    fn parse_filter(&self) -> Result<Filter, String> {
+        Ok(Filter {
+            query: self.filter_value(FilterField::Query).to_string(),
+            duration: Range::parse_minutes(self.filter_value(FilterField::Duration))?,
+            parts: Range::parse_count(self.filter_value(FilterField::Parts))?,
+            pubdate: Range::parse_date(self.filter_value(FilterField::Pubdate))?,
+            fav_time: Range::parse_date(self.filter_value(FilterField::FavTime))?,
+        })
+    }
+
+    /// 筛选条件或排序变化后重新计算显示的项，条件有误时保持原样
+    fn refilter(&mut self) {
+        match self.parse_filter() {
+            Ok(f) => {
+                self.shown = filter::apply(&self.fav_list, &f, self.sort, self.sort_desc);
+                self.filter_msg = String::new();
+            }
+            Err(e) => {
+                // 重新获取列表后旧的下标可能越界
+                let len = self.fav_list.len();
+                self.shown.retain(|i| *i < len);
+                self.filter_msg = e;
+            }
+        }
+    }
+
+    /// 下载`list`，进度由调用者先重置
+    fn options(&self, parts: HashMap<String, HashSet<i64>>) -> DownOptions {
+        DownOptions {
+            force: self.force_download,
+            template: self.template_value.clone(),
+            naming: self.naming,
+            ascii: self.ascii,
+            script: self.script,
+            tags: self.tags,
+            parts,
+            account: self.profile.clone().unwrap_or_default(),
+            ..DownOptions::new(&self.fav_id_value, &self.path)
+        }
+    }
+
+    /// 用输入或导入的Cookie登录，填了账号名时登录成功后保存为账号
+    fn cookie_login(&mut self, cred: Credential) -> Command<Message> {
+        self.credential = Arc::new(cred);
+        let name = self.profile_name.trim();
+        self.profile = if name.is_empty() {
+            None
+        } else {
+            Some(name.to_string())
+        };
+        self.login()
+    }
+
+    /// 用`credential`登录，成功后进入列表页或继续上次的队列
+    fn login(&mut self) -> Command<Message> {
+        self.msg.clear();
+        self.apply_defaults();
+        let account = Command::perform(
+            get_account(Arc::clone(&self.credential)),
+            Message::GotAccount,
+        );
+        if self.resume_queue {
+            self.resume_queue = false;
+            return Command::batch(vec![account, self.resume()]);
+        }
+        self.page = Pages::ListPage;
+        account
+    }
+
+    /// 换成当前账号的默认收藏夹、目录和下载选项
+    fn apply_defaults(&mut self) {
+        let defaults = match self
+            .profile
+            .as_deref()
+            .and_then(|name| self.accounts.get(name))
+            .and_then(|p| p.defaults.clone())
+        {
+            Some(d) => d,
+            None => return,
+        };
+        self.fav_id_value = defaults.fid;
+        self.path = defaults.path;
+        self.template_value = defaults.template;
+        self.naming = defaults.naming;
+        self.ascii = defaults.ascii;
+        self.script = defaults.script;
+        self.tags = defaults.tags;
+    }
+
+    fn download(
+        &mut self,
+        list: Arc<Vec<serde_json::Value>>,
+        opts: DownOptions,
+    ) -> Command<Message> {
+        if let Err(e) = Template::parse(&opts.template) {
+            self.msg = e;
+            return Command::none();
+        }
+        self.downloading = true;
+        self.msg = String::from("下载中...");
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.events = Some(Arc::new(Mutex::new(rx)));
+        self.download_id += 1;
+        self.control = Control::default();
+        let cred = Arc::clone(&self.credential);
+        let control = self.control.clone();
+        Command::perform(
+            async move { start_download(&list, &cred, opts, Some(tx), control).await },
+            Message::Finish,
+        )
+    }
+
+    /// 继续上次的队列：恢复当时的选项，跳过已完成的，失败的重新下载
+    fn resume(&mut self) -> Command<Message> {
+        let opts = match self.journal.opts() {
+            Some(opts) => opts.clone(),
+            None => return Command::none(),
+        };
+        self.fav_id_value = opts.fid.clone();
+        self.path = opts.path.clone();
+        self.force_download = opts.force;
+        self.template_value = opts.template.clone();
+        self.naming = opts.naming;
+        self.ascii = opts.ascii;
+        self.script = opts.script;
+        self.tags = opts.tags;
+        let (list, parts) = self.journal.remaining();
+        self.down_list = Arc::new(list);
+        self.page = Pages::SavePage;
+        self.progress = Progress::default();
+        self.summary = Summary::default();
+        let opts = DownOptions {
+            parts,
+            account: self.profile.clone().unwrap_or_default(),
+            ..opts
+        };
+        self.download(Arc::clone(&self.down_list), opts)
+    }
+
+    /// 清空收藏夹列表和选择，换收藏夹或退出登录时用
+    fn reset_list(&mut self) {
+        self.fav_list.clear();
+        self.list_fid = None;
+        self.fav_lists.clear();
+        self.shown.clear();
+        self.selection = Selection::default();
+        self.last_check = None;
+        for (_, value) in self.filter_inputs.iter_mut() {
+            value.clear();
+        }
+        self.filter_msg.clear();
+        self.expr_value.clear();
+        self.preset_name.clear();
+        self.msg.clear();
+    }
+
+    /// 清空上次下载的结果
+    fn reset_download(&mut self) {
+        self.down_list = Arc::new(vec![]);
+        self.progress = Progress::default();
+        self.summary = Summary::default();
+        self.mirror_msg.clear();
+        self.mirror_pending = None;
+        self.msg.clear();
+    }
+
+    /// 有当前收藏夹的完整列表时才能检查已移出收藏夹的文件，
+    /// 否则清单中这个收藏夹的所有文件都会被当作已移出
+    fn mirror_ready(&self) -> bool {
+        self.list_fid.as_deref() == Some(self.fav_id_value.as_str())
+    }
+
+    /// 开始下载时选的分P，继续上次的队列时用当时的选择
+    fn base_parts(&self) -> HashMap<String, HashSet<i64>> {
+        match self.journal.opts() {
+            Some(opts) => opts.parts.clone(),
+            None => self.selection.parts().clone(),
+        }
+    }
+
+    /// 更新进度和队列记录
+    fn record(&mut self, e: DownEvent) {
+        if let Err(e) = self.journal.record(&e) {
+            eprintln!("队列记录写入失败：{}", e);
+        }
+        self.progress.update(e);
+    }
+
+    fn shown_list(&self) -> Vec<serde_json::Value> {
+        self.shown
+            .iter()
+            .map(|i| self.fav_list[*i].clone())
+            .collect()
+    }
 }
 
 impl Application for App {
@@ -82,6 +458,25 @@ impl Application for App {
         (
             App {
                 page: Pages::Login,
+                account: None,
+                accounts: Accounts::load(),
+                profile: None,
+                account_pick: pick_list::State::default(),
+                profile_input: text_input::State::new(),
+                profile_name: String::new(),
+                save_defaults_button: button::State::new(),
+                vault: None,
+                passphrase_input: text_input::State::new(),
+                passphrase: String::new(),
+                unlock_button: button::State::new(),
+                import_cookies_button: button::State::new(),
+                export_cookies_button: button::State::new(),
+                chromium_key_input: text_input::State::new(),
+                chromium_key: String::new(),
+                back_button: button::State::new(),
+                change_folder_button: button::State::new(),
+                logout_button: button::State::new(),
+                new_batch_button: button::State::new(),
                 qr_login_button: button::State::new(),
                 cookie_login_button: button::State::new(),
                 select_all_button: button::State::new(),
@@ -90,25 +485,74 @@ impl Application for App {
                 final_step_button: button::State::new(),
                 choose_file_button: button::State::new(),
                 start_download_button: button::State::new(),
+                retry_failed_button: button::State::new(),
+                pause_button: button::State::new(),
+                cancel_button: button::State::new(),
+                journal: Journal::load(),
+                resume_queue: false,
+                resume_button: button::State::new(),
+                discard_button: button::State::new(),
                 scroll: scrollable::State::new(),
                 cookie_input: text_input::State::new(),
                 fav_id_input: text_input::State::new(),
                 do_cookie_login_button: button::State::new(),
-                cookie_value: "",
+                cookie_value: String::new(),
+                credential: Arc::new(Credential::default()),
                 cookie_placeholder: String::from("输入SESSDATA"),
-                fav_id_value: "",
+                fav_id_value: String::new(),
                 fav_id_placeholder: String::from("输入收藏夹编号"),
                 get_list_button: button::State::default(),
                 fav_list: vec![],
-                down_list: Box::leak(Vec::new().into()),
-                fav_list_v: HashMap::new(),
+                list_fid: None,
+                down_list: Arc::new(vec![]),
                 fav_lists: vec![],
+                cover_limit: Arc::new(Semaphore::new(8)),
+                shown: vec![],
+                filter_inputs: FILTER_FIELDS
+                    .iter()
+                    .map(|_| (text_input::State::new(), String::new()))
+                    .collect(),
+                filter_msg: String::new(),
+                sort_pick: pick_list::State::default(),
+                sort: SortKey::default(),
+                sort_desc: false,
+                expr_input: text_input::State::new(),
+                expr_value: String::new(),
+                expr_select_button: button::State::new(),
+                expr_deselect_button: button::State::new(),
+                shift: false,
+                last_check: None,
+                selection: Selection::default(),
+                presets: Presets::load(),
+                preset_input: text_input::State::new(),
+                preset_name: String::new(),
+                preset_pick: pick_list::State::default(),
+                save_preset_button: button::State::new(),
                 msg: "".to_string(),
-                path: "",
-                progress: Box::leak(Arc::new(Mutex::new(0.0)).into()),
+                path: String::new(),
                 downloading: false,
-                prog_percent: 0.,
-                start_down_msg: String::from("开始下载"),
+                events: None,
+                download_id: 0,
+                control: Control::default(),
+                summary: Summary::default(),
+                progress: Progress::default(),
+                force_download: false,
+                mirror_preview_button: button::State::new(),
+                mirror_archive_button: button::State::new(),
+                mirror_delete_button: button::State::new(),
+                mirror_confirm_button: button::State::new(),
+                mirror_cancel_button: button::State::new(),
+                mirror_pending: None,
+                invalid_button: button::State::new(),
+                mirror_msg: String::new(),
+                template_input: text_input::State::new(),
+                template_value: String::from(DEFAULT_TEMPLATE),
+                naming_pick: pick_list::State::default(),
+                naming: Strictness::default(),
+                ascii: false,
+                script_pick: pick_list::State::default(),
+                script: Script::default(),
+                tags: false,
             },
             Command::none(),
         )
@@ -118,139 +562,773 @@ impl Application for App {
         String::from("Bili音乐下载")
     }
 
-    fn update(&mut self, message: Self::Message, _: &mut Clipboard) -> Command<Message> {
+    fn subscription(&self) -> Subscription<Message> {
+        let keys = iced_native::subscription::events_with(|event, _| match event {
+            iced_native::Event::Keyboard(iced_native::keyboard::Event::ModifiersChanged(m)) => {
+                Some(Message::ShiftChanged(m.shift))
+            }
+            _ => None,
+        });
+        match &self.events {
+            Some(rx) => Subscription::batch(vec![
+                keys,
+                Subscription::from_recipe(DownEvents {
+                    id: self.download_id,
+                    rx: Arc::clone(rx),
+                })
+                .map(Message::Download),
+            ]),
+            None => keys,
+        }
+    }
+
+    fn update(&mut self, message: Self::Message, clipboard: &mut Clipboard) -> Command<Message> {
         match message {
             Message::QrLoginPressed => Command::none(),
+            Message::ResumeQueue(resume) => {
+                self.resume_queue = resume;
+                if !resume {
+                    if let Err(e) = self.journal.clear() {
+                        eprintln!("队列记录删除失败：{}", e);
+                    }
+                }
+                Command::none()
+            }
             Message::CookieLoginPressed => {
                 self.page = Pages::CookieLogin;
                 Command::none()
             }
             Message::CookieInputChanged(s) => {
-                self.cookie_value = Box::leak(s.into_boxed_str());
+                self.cookie_value = s;
+                Command::none()
+            }
+            Message::ProfileNameChanged(s) => {
+                self.profile_name = s;
+                Command::none()
+            }
+            Message::PassphraseChanged(s) => {
+                self.passphrase = s;
+                Command::none()
+            }
+            Message::Unlock => {
+                let mut v = match Vault::unlock(&self.passphrase) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        self.msg = e;
+                        return Command::none();
+                    }
+                };
+                self.passphrase.clear();
+                self.msg = String::from("已解锁保存的账号");
+                if self.accounts.unlock(&mut v) {
+                    // 先写入凭据库，再从账号文件中去掉明文
+                    if let Err(e) = v.save().and_then(|_| {
+                        self.accounts.forget_plain();
+                        self.accounts.save().map_err(|e| e.to_string())
+                    }) {
+                        self.msg = e;
+                    }
+                }
+                self.vault = Some(v);
                 Command::none()
             }
             Message::FinishCookieLogin => {
-                self.page = Pages::ListPage;
+                self.cookie_login(Credential::from_sessdata(self.cookie_value.trim()))
+            }
+            Message::ChromiumKeyChanged(s) => {
+                self.chromium_key = s;
+                Command::none()
+            }
+            Message::ImportCookies => {
+                Command::perform(choose_cookie_file(), Message::CookieFileChosen)
+            }
+            Message::CookieFileChosen(path) => {
+                let path = match path {
+                    Some(p) => p,
+                    None => return Command::none(),
+                };
+                match cookies::import(&path, &ChromiumKey::parse(&self.chromium_key)) {
+                    Ok(cred) => {
+                        self.cookie_value = cred.sessdata.clone();
+                        self.cookie_login(cred)
+                    }
+                    Err(e) => {
+                        self.msg = e;
+                        Command::none()
+                    }
+                }
+            }
+            Message::ExportCookies => {
+                Command::perform(choose_export_file(), Message::CookieExportChosen)
+            }
+            Message::CookieExportChosen(path) => {
+                if let Some(path) = path {
+                    self.msg = match cookies::export(&path, &self.credential) {
+                        Ok(_) => format!("已导出到{}", path.display()),
+                        Err(e) => e,
+                    };
+                }
+                Command::none()
+            }
+            Message::UseAccount(name) => {
+                if self.downloading {
+                    return Command::none();
+                }
+                let cred = match self.accounts.get(&name) {
+                    Some(p) => p.credential.clone(),
+                    None => return Command::none(),
+                };
+                self.credential = Arc::new(cred);
+                self.profile = Some(name);
+                self.account = None;
+                self.reset_list();
+                self.reset_download();
+                self.login()
+            }
+            Message::GotAccount(res) => {
+                match res {
+                    Ok((mid, uname)) => {
+                        self.account = Some(uname.clone());
+                        // 登录成功才保存账号，顺便更新昵称
+                        if let Some(name) = &self.profile {
+                            self.accounts.upsert(Profile {
+                                mid,
+                                uname,
+                                ..Profile::new(name, (*self.credential).clone())
+                            });
+                            self.accounts.set_current(Some(name));
+                            if let Err(e) = self.accounts.save() {
+                                self.msg = format!("账号保存失败：{}", e);
+                            }
+                            match &mut self.vault {
+                                Some(v) => {
+                                    v.insert(name, (*self.credential).clone());
+                                    if let Err(e) = v.save() {
+                                        self.msg = e;
+                                    }
+                                }
+                                None if vault::AVAILABLE => {
+                                    self.msg = String::from("没有解锁凭据库，账号的Cookie没有保存");
+                                }
+                                None => {
+                                    self.msg = String::from(
+                                        "没有启用secure-store feature，账号的Cookie不会保存",
+                                    );
+                                }
+                            }
+                        }
+                    }
+                    Err(None) if !self.downloading => {
+                        self.account = None;
+                        self.page = Pages::CookieLogin;
+                        self.msg = String::from("Cookie无效或已过期，请重新登录");
+                        // 重新登录后更新这个账号的Cookie
+                        self.profile_name = self.profile.clone().unwrap_or_default();
+                    }
+                    Err(_) => self.account = None,
+                }
+                if self.account.is_some() && !self.credential.refresh_token.is_empty() {
+                    return Command::perform(
+                        refresh_credential(Arc::clone(&self.credential)),
+                        Message::Refreshed,
+                    );
+                }
+                Command::none()
+            }
+            Message::Refreshed(res) => {
+                match res {
+                    Ok(Some(cred)) => {
+                        eprintln!("Cookie已刷新");
+                        self.credential = Arc::new(cred);
+                        if let Some(name) = &self.profile {
+                            if let Some(p) = self.accounts.get_mut(name) {
+                                p.credential = (*self.credential).clone();
+                            }
+                            if let Some(v) = &mut self.vault {
+                                v.insert(name, (*self.credential).clone());
+                                if let Err(e) = v.save() {
+                                    self.msg = e;
+                                }
+                            }
+                        }
+                    }
+                    Ok(None) => {}
+                    // 旧的Cookie可能还能用，只记录下来
+                    Err(e) => eprintln!("{}", e),
+                }
+                Command::none()
+            }
+            Message::SaveDefaults => {
+                let name = match &self.profile {
+                    Some(name) => name.clone(),
+                    None => return Command::none(),
+                };
+                let opts = self.options(HashMap::new());
+                self.msg = match self.accounts.get_mut(&name) {
+                    Some(p) => {
+                        p.set_defaults(&opts);
+                        match self.accounts.save() {
+                            Ok(_) => format!("已保存为账号{}的默认设置", name),
+                            Err(e) => format!("账号保存失败：{}", e),
+                        }
+                    }
+                    None => format!("没有账号：{}", name),
+                };
+                Command::none()
+            }
+            Message::Back => {
+                if !self.downloading {
+                    self.msg.clear();
+                    self.page = Pages::ListPage;
+                }
+                Command::none()
+            }
+            Message::ChangeFolder => {
+                if !self.downloading {
+                    self.reset_list();
+                    self.reset_download();
+                    self.page = Pages::ListPage;
+                }
+                Command::none()
+            }
+            Message::Logout => {
+                if self.downloading {
+                    return Command::none();
+                }
+                self.cookie_value.clear();
+                self.credential = Arc::new(Credential::default());
+                self.account = None;
+                self.profile = None;
+                self.accounts.set_current(None);
+                if let Err(e) = self.accounts.save() {
+                    self.msg = format!("账号保存失败：{}", e);
+                }
+                // 重新锁上凭据库，换账号时要再输入口令
+                self.vault = None;
+                self.accounts.lock();
+                self.fav_id_value.clear();
+                self.reset_list();
+                self.reset_download();
+                self.page = Pages::Login;
                 Command::none()
             }
+            Message::NewBatch => {
+                if self.downloading {
+                    return Command::none();
+                }
+                self.reset_download();
+                self.page = Pages::ListPage;
+                self.update(Message::GetList, clipboard)
+            }
             Message::FavInputChanged(s) => {
-                self.fav_id_value = Box::leak(s.into_boxed_str());
+                self.fav_id_value = s;
                 Command::none()
             }
             Message::GetList => {
                 self.msg = String::from("获取列表...\n（不要重复按OK）");
+                let fid = self.fav_id_value.clone();
                 Command::perform(
-                    get_video_list(self.fav_id_value, self.cookie_value),
-                    Message::GotList,
+                    get_video_list(fid.clone(), Arc::clone(&self.credential)),
+                    move |l| Message::GotList(fid.clone(), l),
                 )
             }
-            Message::GotList(l) => {
+            Message::GotList(fid, l) => {
                 self.msg = String::from("获取完成\n把鼠标移到OK下面即可看到滚动条");
-                if let Some(l) = l {
-                    self.fav_list_v = HashMap::new();
-                    self.fav_lists = vec![];
-                    l.iter().enumerate().for_each(|(i, v)| {
-                        self.fav_list_v.insert(i, true);
-                        self.fav_lists.push(Check::new(
-                            v["title"]
-                                .as_str()
-                                .unwrap_or_default()
-                                .parse()
-                                .unwrap_or_default(),
-                        ));
-                    });
-                    self.fav_list = l;
+                let l = match l {
+                    Some(l) => l,
+                    None => return Command::none(),
+                };
+                self.list_fid = Some(fid);
+                self.selection = Selection::default();
+                self.fav_lists = l.iter().map(Check::new).collect();
+                self.fav_list = l;
+                self.last_check = None;
+                self.refilter();
+                // 封面在后台加载，加载完成前显示空白
+                Command::batch(self.fav_list.iter().enumerate().filter_map(|(i, v)| {
+                    let url = v["cover"].as_str()?.to_string();
+                    let bvid = v["bvid"].as_str().unwrap_or_default().to_string();
+                    let limit = Arc::clone(&self.cover_limit);
+                    Some(Command::perform(load_cover(url, limit), move |p| {
+                        Message::GotCover(i, bvid.clone(), p)
+                    }))
+                }))
+            }
+            Message::GotCover(i, bvid, p) => {
+                // 封面加载期间可能重新获取了列表
+                if let (Some(check), Some(p)) = (self.fav_lists.get_mut(i), p) {
+                    if check.bvid == bvid {
+                        check.cover = Some(image::Handle::from_path(p));
+                    }
                 }
                 Command::none()
             }
-            Message::Check(i, v) => {
-                self.fav_lists[i].update(v);
+            Message::Check(i, CheckMessage::Expand) => {
+                let check = &mut self.fav_lists[i];
+                check.loading = true;
+                let cred = Arc::clone(&self.credential);
+                let bvid = check.bvid.clone();
+                Command::perform(get_parts(bvid.clone(), cred), move |p| {
+                    Message::GotParts(i, bvid.clone(), p)
+                })
+            }
+            Message::Check(i, CheckMessage::Check(v)) => {
+                let pos = |i| self.shown.iter().position(|s| *s == i);
+                let range = match (self.shift, self.last_check.and_then(pos), pos(i)) {
+                    (true, Some(a), Some(b)) => self.shown[a.min(b)..=a.max(b)].to_vec(),
+                    _ => vec![i],
+                };
+                for j in range {
+                    self.selection.set(&self.fav_lists[j].bvid, v);
+                }
+                self.last_check = Some(i);
+                Command::none()
+            }
+            Message::Check(i, CheckMessage::Part(j, v)) => {
+                let check = &self.fav_lists[i];
+                let all: Vec<i64> = check.parts.iter().map(|p| p.cid).collect();
+                self.selection.set_part(&check.bvid, all[j], v, &all);
+                Command::none()
+            }
+            Message::GotParts(i, bvid, p) => {
+                // 分P加载期间可能换了收藏夹或重新获取了列表
+                let check = match self.fav_lists.get_mut(i) {
+                    Some(check) if check.bvid == bvid => check,
+                    _ => return Command::none(),
+                };
+                check.loading = false;
+                match p {
+                    Some(p) => check.parts = p.iter().map(Part::new).collect(),
+                    None => self.msg = String::from("分P获取失败"),
+                }
                 Command::none()
             }
+            // 全选、反选和全不选只作用于筛选后显示的项
             Message::SelectAll => {
-                self.fav_lists.iter_mut().for_each(|v| v.v = true);
+                self.selection.select_all(&self.shown_list());
                 Command::none()
             }
             Message::Clear => {
-                self.fav_lists.iter_mut().for_each(|v| v.v = false);
+                self.selection.clear(&self.shown_list());
                 Command::none()
             }
             Message::Fanxuan => {
-                self.fav_lists.iter_mut().for_each(|v| v.v = !v.v);
+                self.selection.invert(&self.shown_list());
                 Command::none()
             }
-            Message::FinalStep => {
-                let mut down_list: Vec<serde_json::Value> = vec![];
-                self.fav_list.iter().enumerate().for_each(|(i, v)| {
-                    if self.fav_list_v[&i] == true {
-                        down_list.push(v.clone());
+            Message::FilterChanged(field, s) => {
+                self.filter_inputs[field as usize].1 = s;
+                self.refilter();
+                Command::none()
+            }
+            Message::SortChanged(k) => {
+                self.sort = k;
+                self.refilter();
+                Command::none()
+            }
+            Message::SortDescChanged(v) => {
+                self.sort_desc = v;
+                self.refilter();
+                Command::none()
+            }
+            Message::ExprChanged(s) => {
+                self.expr_value = s;
+                Command::none()
+            }
+            Message::ApplyExpr(select) => {
+                match Expr::parse(&self.expr_value) {
+                    Ok(expr) => {
+                        for i in self.shown.iter() {
+                            if expr.matches(*i, &self.fav_list[*i]) {
+                                self.selection.set(&self.fav_lists[*i].bvid, select);
+                            }
+                        }
+                        self.filter_msg = String::new();
                     }
-                });
-                self.down_list = Box::leak(down_list.into());
+                    Err(e) => self.filter_msg = e,
+                }
+                Command::none()
+            }
+            Message::ShiftChanged(v) => {
+                self.shift = v;
+                Command::none()
+            }
+            Message::PresetNameChanged(s) => {
+                self.preset_name = s;
+                Command::none()
+            }
+            Message::SavePreset => {
+                let name = self.preset_name.trim();
+                if name.is_empty() {
+                    self.msg = String::from("请输入预设名");
+                    return Command::none();
+                }
+                self.presets
+                    .insert(&self.fav_id_value, name, self.selection.clone());
+                self.msg = match self.presets.save() {
+                    Ok(_) => format!("已保存预设：{}", name),
+                    Err(e) => format!("预设保存失败：{}", e),
+                };
+                Command::none()
+            }
+            Message::ApplyPreset(name) => {
+                if let Some(s) = self.presets.get(&self.fav_id_value, &name) {
+                    self.selection = s.clone();
+                    self.preset_name = name;
+                }
+                Command::none()
+            }
+            Message::FinalStep => {
+                // 已失效的视频也交给下载，在结果中显示为跳过
+                self.down_list = Arc::new(self.selection.filter(&self.fav_list));
                 self.msg = "".into();
                 self.page = Pages::SavePage;
                 Command::none()
             }
             Message::ChooseFile => Command::perform(choose_file(), Message::ChangePath),
             Message::StartDown => {
-                if !self.downloading {
-                    self.downloading = true;
-                    self.msg = String::from("下载中...但进度条不会自己动");
-                    self.start_down_msg = String::from("刷新进度条");
-                    Command::perform(
-                        start_download(
-                            &self.down_list,
-                            self.cookie_value,
-                            &self.path,
-                            Arc::clone(self.progress),
-                        ),
-                        Message::Finish,
-                    )
-                } else {
-                    Command::perform(open_mu(self.progress), Message::ChangeProg)
+                if self.downloading {
+                    return Command::none();
+                }
+                self.progress = Progress::default();
+                self.summary = Summary::default();
+                let opts = self.options(self.selection.parts().clone());
+                if let Err(e) = self.journal.start(&opts, &self.down_list) {
+                    eprintln!("队列记录写入失败：{}", e);
+                }
+                self.download(Arc::clone(&self.down_list), opts)
+            }
+            Message::Retry(bvid, cid) => {
+                if self.downloading {
+                    return Command::none();
+                }
+                let video = match self.down_list.iter().find(|v| filter::bvid(v) == bvid) {
+                    Some(v) => v.clone(),
+                    None => return Command::none(),
+                };
+                // cid为0时是整个视频失败了，按原来选的分P重试
+                let mut parts = self.base_parts();
+                if cid != 0 {
+                    parts.insert(bvid, std::iter::once(cid).collect());
+                }
+                self.progress.restart();
+                let opts = self.options(parts);
+                self.download(Arc::new(vec![video]), opts)
+            }
+            Message::RetryFailed => {
+                if self.downloading {
+                    return Command::none();
+                }
+                let (list, parts) = self.summary.retry_list(&self.down_list, &self.base_parts());
+                if list.is_empty() {
+                    return Command::none();
+                }
+                self.progress.restart();
+                let opts = self.options(parts);
+                self.download(Arc::new(list), opts)
+            }
+            Message::Pause(bvid) => {
+                match bvid {
+                    Some(bvid) => self.control.pause_item(&bvid),
+                    None => self.control.pause(),
+                }
+                Command::none()
+            }
+            Message::Resume(bvid) => {
+                match bvid {
+                    Some(bvid) => self.control.resume_item(&bvid),
+                    None => self.control.resume(),
                 }
+                Command::none()
             }
-            Message::Finish(_) => {
-                self.msg = String::from("下载完成！");
+            Message::Cancel(bvid) => {
+                match bvid {
+                    Some(bvid) => self.control.cancel_item(&bvid),
+                    None => self.control.cancel(),
+                }
+                Command::none()
+            }
+            Message::OpenLocation(rel) => {
+                if let Err(e) = open_location(&std::path::Path::new(&self.path).join(rel)) {
+                    self.msg = format!("无法打开文件位置：{}", e);
+                }
+                Command::none()
+            }
+            Message::Download(e) => {
+                self.record(e);
+                Command::none()
+            }
+            Message::Finish(res) => {
+                // 订阅可能还没收完最后的事件
+                if let Some(rx) = self.events.take() {
+                    if let Ok(mut rx) = rx.try_lock() {
+                        while let Ok(e) = rx.try_recv() {
+                            self.record(e);
+                        }
+                    }
+                }
+                self.downloading = false;
+                // 全部完成后不再需要队列记录，有失败的留着下次重试
+                if self.journal.pending() == 0 {
+                    if let Err(e) = self.journal.clear() {
+                        eprintln!("队列记录删除失败：{}", e);
+                    }
+                }
+                let summary = match res {
+                    Ok(summary) => summary,
+                    Err(e) => {
+                        self.msg = e;
+                        return Command::none();
+                    }
+                };
+                self.summary.update(summary);
+                let title = if self.control.is_queue_cancelled() {
+                    "已取消下载"
+                } else if self.summary.ok() {
+                    "下载完成！"
+                } else {
+                    "下载完成，但有失败的项"
+                };
+                self.msg = format!("{}\n{}", title, self.summary);
                 Command::none()
             }
             Message::ChangePath(u) => {
-                self.path = Box::leak(u.as_str().into());
+                self.path = u;
+                Command::none()
+            }
+            Message::ForceChanged(v) => {
+                self.force_download = v;
+                Command::none()
+            }
+            Message::TemplateChanged(s) => {
+                self.template_value = s;
+                Command::none()
+            }
+            Message::NamingChanged(n) => {
+                self.naming = n;
+                Command::none()
+            }
+            Message::AsciiChanged(v) => {
+                self.ascii = v;
+                Command::none()
+            }
+            Message::ScriptChanged(v) => {
+                self.script = v;
+                Command::none()
+            }
+            Message::TagsChanged(v) => {
+                self.tags = v;
+                Command::none()
+            }
+            Message::InvalidReport => {
+                let invalid = Manifest::load(&self.path).invalid(&self.fav_list);
+                self.mirror_msg = if invalid.is_empty() {
+                    String::from("没有已失效的视频")
+                } else {
+                    invalid
+                        .iter()
+                        .map(|v| match v.local.first() {
+                            Some(e) => format!(
+                                "已失效：{} {} - {}（本地：{}）",
+                                v.bvid,
+                                e.title,
+                                e.author,
+                                v.local
+                                    .iter()
+                                    .map(|e| e.path.as_str())
+                                    .collect::<Vec<&str>>()
+                                    .join("、")
+                            ),
+                            None => format!("已失效：{}（本地没有记录）", v.bvid),
+                        })
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                };
+                Command::none()
+            }
+            Message::Mirror(action, dry_run) => {
+                if !self.mirror_ready() {
+                    self.mirror_pending = None;
+                    self.mirror_msg =
+                        String::from("没有获取这个收藏夹的列表，不能检查已移出收藏夹的文件");
+                    return Command::none();
+                }
+                // 只执行预览过的操作
+                if !dry_run && self.mirror_pending != Some(action) {
+                    return Command::none();
+                }
+                let mut manifest = Manifest::load(&self.path);
+                let res = manifest.mirror(&self.fav_id_value, &self.fav_list, action, dry_run);
+                self.mirror_pending = match action {
+                    MirrorAction::Archive | MirrorAction::Delete if dry_run && !res.is_empty() => {
+                        Some(action)
+                    }
+                    _ => None,
+                };
+                self.mirror_msg = if res.is_empty() {
+                    String::from("没有已移出收藏夹的文件")
+                } else {
+                    let verb = match (action, dry_run) {
+                        (MirrorAction::Archive, false) => "已移到_removed",
+                        (MirrorAction::Delete, false) => "已删除",
+                        (MirrorAction::Archive, true) => "将移到_removed",
+                        (MirrorAction::Delete, true) => "将删除",
+                        _ => "已移出收藏夹",
+                    };
+                    res.iter()
+                        .map(|(e, r)| match r {
+                            Ok(_) => format!("{}：{}", verb, e.path),
+                            Err(err) => format!("失败：{}（{}）", e.path, err),
+                        })
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                };
                 Command::none()
             }
-            Message::ChangeProg(p) => {
-                self.prog_percent = p;
+            Message::MirrorCancel => {
+                self.mirror_pending = None;
+                self.mirror_msg.clear();
                 Command::none()
             }
         }
     }
 
     fn view(&mut self) -> Element<Message> {
+        // 构建界面时控件的状态会被可变借用，先算好
+        let mirror_ready = self.mirror_ready();
         let this_page: Element<_> = match self.page {
-            Pages::Login => Row::new()
-                .spacing(20)
-                .push(
-                    Button::new(&mut self.qr_login_button, Text::new("二维码登录（没做）"))
-                        .on_press(Message::QrLoginPressed),
-                )
-                .push(
-                    Button::new(&mut self.cookie_login_button, Text::new("Cookie登录"))
-                        .on_press(Message::CookieLoginPressed),
-                )
-                .into(),
-            Pages::CookieLogin => Row::new()
-                .spacing(20)
-                .push(TextInput::new(
-                    &mut self.cookie_input,
-                    &self.cookie_placeholder,
-                    &self.cookie_value,
-                    Message::CookieInputChanged,
-                ))
-                .push(
-                    Button::new(&mut self.do_cookie_login_button, Text::new("登录"))
-                        .on_press(Message::FinishCookieLogin),
-                )
-                .into(),
+            Pages::Login => {
+                let login = Row::new()
+                    .spacing(20)
+                    .push(
+                        Button::new(&mut self.qr_login_button, Text::new("二维码登录（没做）"))
+                            .on_press(Message::QrLoginPressed),
+                    )
+                    .push(
+                        Button::new(&mut self.cookie_login_button, Text::new("Cookie登录"))
+                            .on_press(Message::CookieLoginPressed),
+                    );
+                let locked = vault::AVAILABLE && self.vault.is_none();
+                let unlock: Element<Message> =
+                    if locked && (Vault::exists() || !self.accounts.names().is_empty()) {
+                        passphrase_row(
+                            &mut self.passphrase_input,
+                            &self.passphrase,
+                            &mut self.unlock_button,
+                        )
+                        .into()
+                    } else {
+                        Space::with_height(Length::Shrink).into()
+                    };
+                let names = self.accounts.names();
+                let saved: Element<Message> = if names.is_empty() || locked {
+                    Space::with_height(Length::Shrink).into()
+                } else {
+                    Row::new()
+                        .spacing(10)
+                        .push(Text::new("用保存的账号登录："))
+                        .push(PickList::new(
+                            &mut self.account_pick,
+                            names,
+                            None,
+                            Message::UseAccount,
+                        ))
+                        .into()
+                };
+                let pending = self.journal.pending();
+                let resume: Element<Message> = if self.resume_queue {
+                    Text::new(format!("登录后继续下载{}个视频", pending)).into()
+                } else if pending > 0 {
+                    Column::new()
+                        .push(Text::new(format!(
+                            "上次的下载队列还有{}个视频没有完成",
+                            pending
+                        )))
+                        .push(
+                            Row::new()
+                                .spacing(20)
+                                .push(
+                                    Button::new(&mut self.resume_button, Text::new("登录后继续"))
+                                        .on_press(Message::ResumeQueue(true)),
+                                )
+                                .push(
+                                    Button::new(&mut self.discard_button, Text::new("放弃"))
+                                        .on_press(Message::ResumeQueue(false)),
+                                ),
+                        )
+                        .into()
+                } else {
+                    Space::with_height(Length::Shrink).into()
+                };
+                Column::new()
+                    .spacing(20)
+                    .push(login)
+                    .push(unlock)
+                    .push(saved)
+                    .push(resume)
+                    .push(Text::new(&self.msg))
+                    .into()
+            }
+            Pages::CookieLogin => {
+                // 要保存账号时先解锁凭据库
+                let unlock: Element<Message> = if vault::AVAILABLE
+                    && self.vault.is_none()
+                    && !self.profile_name.trim().is_empty()
+                {
+                    passphrase_row(
+                        &mut self.passphrase_input,
+                        &self.passphrase,
+                        &mut self.unlock_button,
+                    )
+                    .into()
+                } else {
+                    Space::with_height(Length::Shrink).into()
+                };
+                Column::new()
+                    .push(
+                        Row::new()
+                            .spacing(20)
+                            .push(TextInput::new(
+                                &mut self.cookie_input,
+                                &self.cookie_placeholder,
+                                &self.cookie_value,
+                                Message::CookieInputChanged,
+                            ))
+                            .push(
+                                Button::new(&mut self.do_cookie_login_button, Text::new("登录"))
+                                    .on_press(Message::FinishCookieLogin),
+                            ),
+                    )
+                    .push(TextInput::new(
+                        &mut self.profile_input,
+                        "保存为账号（账号名，可不填）",
+                        &self.profile_name,
+                        Message::ProfileNameChanged,
+                    ))
+                    .push(unlock)
+                    .push(
+                        Row::new()
+                            .spacing(10)
+                            .push(
+                                Button::new(
+                                    &mut self.import_cookies_button,
+                                    Text::new("从浏览器或cookies.txt导入..."),
+                                )
+                                .on_press(Message::ImportCookies),
+                            )
+                            .push(
+                                TextInput::new(
+                                    &mut self.chromium_key_input,
+                                    "Chromium的密钥或密码（可不填）",
+                                    &self.chromium_key,
+                                    Message::ChromiumKeyChanged,
+                                )
+                                .password(),
+                            ),
+                    )
+                    .push(Text::new(&self.msg))
+                    .into()
+            }
             Pages::ListPage => {
                 let res = Column::new()
                     .push(
@@ -267,21 +1345,85 @@ impl Application for App {
                             ),
                     )
                     .push(Text::new(&self.msg));
-                let len = self.fav_list.len().clone();
-                let list = Column::new().push(self.fav_lists.iter_mut().enumerate().fold(
-                    Column::new().spacing(5),
-                    |col, (index, check)| {
-                        // We display the counter
-                        let element: Element<CheckMessage> = check.view().into();
-
-                        col.push(Text::new(format!("{}", index))).push(
-                            // Here we turn our `Element<counter::Message>` into
-                            // an `Element<Message>` by combining the `index` and the
-                            // message of the `element`.
-                            element.map(move |message| Message::Check(index, message)),
+                let len = self.fav_list.len();
+                let selection = &self.selection;
+                // 按筛选、排序后的顺序取出各行
+                let mut checks: Vec<Option<&mut Check>> =
+                    self.fav_lists.iter_mut().map(Some).collect();
+                let list = self
+                    .shown
+                    .iter()
+                    .fold(Column::new().spacing(5), |col, &index| {
+                        let check = match checks[index].take() {
+                            Some(c) => c,
+                            None => return col,
+                        };
+                        let element: Element<CheckMessage> = check.view(selection);
+                        col.push(
+                            Row::new()
+                                .spacing(10)
+                                .push(Text::new(format!("{}", index)))
+                                // 把`Element<CheckMessage>`加上下标转成`Element<Message>`
+                                .push(element.map(move |message| Message::Check(index, message))),
+                        )
+                    });
+                let mut filters = Column::new();
+                if len > 0 {
+                    let inputs = self
+                        .filter_inputs
+                        .iter_mut()
+                        .zip(FILTER_FIELDS.iter())
+                        .fold(Row::new(), |row, ((state, value), (field, placeholder))| {
+                            let field = *field;
+                            row.push(TextInput::new(state, placeholder, value, move |s| {
+                                Message::FilterChanged(field, s)
+                            }))
+                        });
+                    filters = filters
+                        .push(inputs)
+                        .push(
+                            Row::new()
+                                .push(TextInput::new(
+                                    &mut self.expr_input,
+                                    "选择表达式，如 0-19, 35, 50- 或 duration < 10min & upper = xxx",
+                                    &self.expr_value,
+                                    Message::ExprChanged,
+                                ))
+                                .push(
+                                    Button::new(&mut self.expr_select_button, Text::new("选中"))
+                                        .on_press(Message::ApplyExpr(true)),
+                                )
+                                .push(
+                                    Button::new(
+                                        &mut self.expr_deselect_button,
+                                        Text::new("取消选中"),
+                                    )
+                                    .on_press(Message::ApplyExpr(false)),
+                                ),
                         )
-                    },
-                ));
+                        .push(
+                        Row::new()
+                            .spacing(10)
+                            .push(Text::new("排序："))
+                            .push(PickList::new(
+                                &mut self.sort_pick,
+                                &SortKey::ALL[..],
+                                Some(self.sort),
+                                Message::SortChanged,
+                            ))
+                            .push(Checkbox::new(
+                                self.sort_desc,
+                                "倒序",
+                                Message::SortDescChanged,
+                            ))
+                            .push(Text::new(format!(
+                                "显示{}/{}项 {}",
+                                self.shown.len(),
+                                len,
+                                self.filter_msg
+                            ))),
+                    );
+                }
                 let mut options = Row::new();
                 if len > 0 {
                     options = options
@@ -302,30 +1444,223 @@ impl Application for App {
                                 .on_press(Message::FinalStep),
                         );
                 }
-                res.push(options).push(list).into()
+                let mut presets = Row::new();
+                if len > 0 {
+                    let names = self.presets.names(&self.fav_id_value);
+                    let current = names.iter().find(|n| **n == self.preset_name).cloned();
+                    presets = presets
+                        .push(Text::new("预设："))
+                        .push(PickList::new(
+                            &mut self.preset_pick,
+                            names,
+                            current,
+                            Message::ApplyPreset,
+                        ))
+                        .push(TextInput::new(
+                            &mut self.preset_input,
+                            "预设名",
+                            &self.preset_name,
+                            Message::PresetNameChanged,
+                        ))
+                        .push(
+                            Button::new(&mut self.save_preset_button, Text::new("保存选择"))
+                                .on_press(Message::SavePreset),
+                        );
+                }
+                res.push(filters)
+                    .push(options)
+                    .push(presets)
+                    .push(list)
+                    .into()
             }
             Pages::SavePage => Column::new()
+                .push({
+                    let mut back = Button::new(&mut self.back_button, Text::new("返回选择"));
+                    let mut new_batch =
+                        Button::new(&mut self.new_batch_button, Text::new("开始新的下载"));
+                    if !self.downloading {
+                        back = back.on_press(Message::Back);
+                        new_batch = new_batch.on_press(Message::NewBatch);
+                    }
+                    Row::new().spacing(10).push(back).push(new_batch)
+                })
                 .push(Text::new(self.path.clone()))
                 .push(
                     Button::new(&mut self.choose_file_button, Text::new("浏览..."))
                         .on_press(Message::ChooseFile),
                 )
+                .push(Text::new(
+                    "文件名模板（/表示子目录，[...]中有空值时省略）：",
+                ))
+                .push(TextInput::new(
+                    &mut self.template_input,
+                    DEFAULT_TEMPLATE,
+                    &self.template_value,
+                    Message::TemplateChanged,
+                ))
                 .push(
-                    Button::new(
-                        &mut self.start_download_button,
-                        Text::new(&self.start_down_msg),
-                    )
-                    .on_press(Message::StartDown),
+                    Row::new()
+                        .push(Text::new("文件名兼容："))
+                        .push(PickList::new(
+                            &mut self.naming_pick,
+                            &Strictness::ALL[..],
+                            Some(self.naming),
+                            Message::NamingChanged,
+                        )),
                 )
+                .push(Checkbox::new(
+                    self.ascii,
+                    "文件名只用ASCII（拼音、罗马字）",
+                    Message::AsciiChanged,
+                ))
+                .push(if translit::AVAILABLE {
+                    Row::new().push(Text::new("简繁转换：")).push(PickList::new(
+                        &mut self.script_pick,
+                        &Script::ALL[..],
+                        Some(self.script),
+                        Message::ScriptChanged,
+                    ))
+                } else {
+                    Row::new()
+                })
+                .push(Checkbox::new(
+                    self.tags || self.ascii,
+                    "写入标签（需要ffmpeg）",
+                    Message::TagsChanged,
+                ))
+                .push(Checkbox::new(
+                    self.force_download,
+                    "强制重新下载",
+                    Message::ForceChanged,
+                ))
+                .push(match &self.profile {
+                    Some(name) => Row::new().push(
+                        Button::new(
+                            &mut self.save_defaults_button,
+                            Text::new(format!("保存为账号{}的默认设置", name)),
+                        )
+                        .on_press(Message::SaveDefaults),
+                    ),
+                    None => Row::new(),
+                })
+                .push({
+                    let mut row = Row::new().spacing(10).push(
+                        Button::new(&mut self.start_download_button, Text::new("开始下载"))
+                            .on_press(Message::StartDown),
+                    );
+                    if self.downloading {
+                        let pause = if self.control.is_queue_paused() {
+                            Button::new(&mut self.pause_button, Text::new("全部继续"))
+                                .on_press(Message::Resume(None))
+                        } else {
+                            Button::new(&mut self.pause_button, Text::new("全部暂停"))
+                                .on_press(Message::Pause(None))
+                        };
+                        row = row.push(pause).push(
+                            Button::new(&mut self.cancel_button, Text::new("全部取消"))
+                                .on_press(Message::Cancel(None)),
+                        );
+                    } else if !self.summary.ok() {
+                        let label = format!("只重试失败的项（{}）", self.summary.failed.len());
+                        row = row.push(
+                            Button::new(&mut self.retry_failed_button, Text::new(label))
+                                .on_press(Message::RetryFailed),
+                        );
+                    }
+                    row
+                })
                 .push(Text::new(&self.msg))
-                .push(Text::new(format!(
-                    "进度：{:.3}%",
-                    100. * self.prog_percent / self.down_list.len() as f64
-                )))
+                .push(Text::new(self.progress.to_string()))
+                .push(self.progress.view(if self.downloading {
+                    Some(&self.control)
+                } else {
+                    None
+                }))
+                .push({
+                    // 没有当前收藏夹的列表时不能用；移到_removed和删除先预览
+                    let mut preview = Button::new(
+                        &mut self.mirror_preview_button,
+                        Text::new("检查已移出收藏夹的文件"),
+                    );
+                    let mut archive =
+                        Button::new(&mut self.mirror_archive_button, Text::new("移到_removed"));
+                    let mut delete = Button::new(&mut self.mirror_delete_button, Text::new("删除"));
+                    if mirror_ready {
+                        preview = preview.on_press(Message::Mirror(MirrorAction::Report, true));
+                        archive = archive.on_press(Message::Mirror(MirrorAction::Archive, true));
+                        delete = delete.on_press(Message::Mirror(MirrorAction::Delete, true));
+                    }
+                    let mut row = Row::new().push(preview).push(archive).push(delete);
+                    if let (true, Some(action)) = (mirror_ready, self.mirror_pending) {
+                        let label = match action {
+                            MirrorAction::Delete => "确认删除",
+                            _ => "确认移到_removed",
+                        };
+                        row = row
+                            .push(
+                                Button::new(&mut self.mirror_confirm_button, Text::new(label))
+                                    .on_press(Message::Mirror(action, false)),
+                            )
+                            .push(
+                                Button::new(&mut self.mirror_cancel_button, Text::new("取消"))
+                                    .on_press(Message::MirrorCancel),
+                            );
+                    }
+                    row
+                })
+                .push(
+                    Button::new(&mut self.invalid_button, Text::new("检查已失效的视频"))
+                        .on_press(Message::InvalidReport),
+                )
+                .push(Text::new(&self.mirror_msg))
                 .into(),
         };
-        let scrollable = Scrollable::new(&mut self.scroll)
-            .push(Container::new(this_page).width(Length::Fill).center_x());
+        // 登录后在每页顶部显示账号，下载中不能换收藏夹或退出
+        let header: Element<_> = match self.page {
+            Pages::ListPage | Pages::SavePage => {
+                let account = match &self.account {
+                    Some(name) => format!("当前账号：{}", name),
+                    None => String::from("当前账号：（未获取）"),
+                };
+                let mut change_folder =
+                    Button::new(&mut self.change_folder_button, Text::new("更换收藏夹"));
+                let mut logout = Button::new(&mut self.logout_button, Text::new("退出登录"));
+                if !self.downloading {
+                    change_folder = change_folder.on_press(Message::ChangeFolder);
+                    logout = logout.on_press(Message::Logout);
+                }
+                let mut row = Row::new().spacing(10).push(Text::new(account));
+                if let Some(w) = account::expiry_warning(&self.credential) {
+                    row = row.push(Text::new(w).color(Color::from_rgb(0.8, 0.2, 0.2)));
+                }
+                // 下载中换账号会打断下载，不显示
+                let names = self.accounts.names();
+                if !self.downloading && !names.is_empty() {
+                    row = row.push(Text::new("切换：")).push(PickList::new(
+                        &mut self.account_pick,
+                        names,
+                        self.profile.clone(),
+                        Message::UseAccount,
+                    ));
+                }
+                row.push(change_folder)
+                    .push(
+                        Button::new(
+                            &mut self.export_cookies_button,
+                            Text::new("导出cookies.txt"),
+                        )
+                        .on_press(Message::ExportCookies),
+                    )
+                    .push(logout)
+                    .into()
+            }
+            _ => Space::with_height(Length::Shrink).into(),
+        };
+        let scrollable = Scrollable::new(&mut self.scroll).push(
+            Container::new(Column::new().spacing(10).push(header).push(this_page))
+                .width(Length::Fill)
+                .center_x(),
+        );
 
         Container::new(scrollable)
             .height(Length::Fill)
@@ -337,137 +1672,573 @@ impl Application for App {
 #[derive(Clone, Debug)]
 enum CheckMessage {
     Check(bool),
+    Expand,
+    Part(usize, bool),
 }
 
 struct Check {
     title: String,
-    v: bool,
+    bvid: String,
+    upper: String,
+    /// 总时长（秒）
+    duration: i64,
+    /// 分P数
+    page: i64,
+    plays: i64,
+    pubdate: i64,
+    invalid: bool,
+    cover: Option<image::Handle>,
+    /// 展开后的分P，没展开时为空
+    parts: Vec<Part>,
+    loading: bool,
+    expand_button: button::State,
+}
+
+struct Part {
+    cid: i64,
+    name: String,
+    /// 时长（秒）
+    duration: i64,
+}
+
+impl Part {
+    fn new(p: &serde_json::Value) -> Part {
+        Part {
+            cid: p["cid"].as_i64().unwrap_or_default(),
+            name: p["part"].as_str().unwrap_or_default().to_string(),
+            duration: p["duration"].as_i64().unwrap_or_default(),
+        }
+    }
 }
 
 impl Check {
-    fn new(title: String) -> Self {
-        Check { title, v: true }
+    fn new(v: &serde_json::Value) -> Self {
+        Check {
+            title: v["title"].as_str().unwrap_or_default().to_string(),
+            bvid: v["bvid"].as_str().unwrap_or_default().to_string(),
+            upper: filter::upper(v).to_string(),
+            duration: filter::duration(v),
+            page: filter::parts(v),
+            plays: filter::plays(v),
+            pubdate: filter::pubdate(v),
+            invalid: filter::is_invalid(v),
+            cover: None,
+            parts: vec![],
+            loading: false,
+            expand_button: button::State::new(),
+        }
     }
 
-    fn update(&mut self, message: CheckMessage) {
-        match message {
-            CheckMessage::Check(v) => {
-                self.v = v;
+    fn view(&mut self, selection: &Selection) -> Element<CheckMessage> {
+        // 已失效的视频不能下载，显示成灰色
+        let mut row = if self.invalid {
+            Row::new()
+                .spacing(10)
+                .push(Text::new(&self.title).color(Color::from_rgb(0.6, 0.6, 0.6)))
+        } else {
+            Row::new().spacing(10).push(Checkbox::new(
+                selection.is_selected(&self.bvid),
+                &self.title,
+                CheckMessage::Check,
+            ))
+        };
+        if self.page > 1 && self.parts.is_empty() {
+            let label = if self.loading {
+                String::from("获取中...")
+            } else {
+                format!("展开{}P", self.page)
+            };
+            let mut expand = Button::new(&mut self.expand_button, Text::new(label));
+            if !self.loading {
+                expand = expand.on_press(CheckMessage::Expand);
+            }
+            row = row.push(expand);
+        }
+        let parts = self
+            .parts
+            .iter()
+            .enumerate()
+            .fold(Column::new(), |col, (i, p)| {
+                col.push(Checkbox::new(
+                    selection.is_part_selected(&self.bvid, p.cid),
+                    format!("P{} {}（{}）", i + 1, p.name, format_duration(p.duration)),
+                    move |v| CheckMessage::Part(i, v),
+                ))
+            });
+        let mut info = format!(
+            "{} · {} · {}P · 播放{} · {}",
+            self.upper,
+            format_duration(self.duration),
+            self.page,
+            format_count(self.plays),
+            template::format_time(self.pubdate, "%Y-%m-%d")
+        );
+        if self.invalid {
+            info = format!("[已失效] {}", info);
+        }
+        let cover: Element<CheckMessage> = match &self.cover {
+            Some(h) => Image::new(h.clone())
+                .width(Length::Units(COVER_WIDTH))
+                .into(),
+            None => Space::with_width(Length::Units(COVER_WIDTH)).into(),
+        };
+        Row::new()
+            .spacing(10)
+            .push(cover)
+            .push(
+                Column::new().push(row).push(Text::new(info).size(16)).push(
+                    Row::new()
+                        .push(Space::with_width(Length::Units(30)))
+                        .push(parts),
+                ),
+            )
+            .into()
+    }
+}
+
+/// 列表中封面缩略图的宽度
+const COVER_WIDTH: u16 = 96;
+
+/// 把播放数显示成`1.2万`这样
+fn format_count(n: i64) -> String {
+    if n >= 100_000_000 {
+        format!("{:.1}亿", n as f64 / 1e8)
+    } else if n >= 10_000 {
+        format!("{:.1}万", n as f64 / 1e4)
+    } else {
+        n.to_string()
+    }
+}
+
+/// 把秒数显示成`m:ss`或`h:mm:ss`
+fn format_duration(secs: i64) -> String {
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs % 3600 / 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// 下载页显示的进度，由`DownEvent`更新
+#[derive(Default)]
+struct Progress {
+    /// 队列中每个分P的状态，还没取得分P列表的视频占一行（cid为0）
+    rows: Vec<PartRow>,
+    /// `(bvid, cid)`在`rows`中的位置
+    index: HashMap<(String, i64), usize>,
+    scroll: scrollable::State,
+    videos: usize,
+    done: usize,
+    failed: usize,
+    /// 正在下载的文件
+    current: String,
+    /// 每个分P已下载的字节数
+    part_bytes: HashMap<i64, u64>,
+    /// 正在下载的分P的进度
+    current_bytes: u64,
+    current_total: Option<u64>,
+    /// 本次下载的总字节数
+    bytes: u64,
+    started: Option<Instant>,
+    /// 上次计算速度的时间和当时的总字节数
+    sample: Option<(Instant, u64)>,
+    speed: f64,
+}
+
+impl Progress {
+    fn update(&mut self, e: DownEvent) {
+        let now = Instant::now();
+        match e {
+            DownEvent::Started { videos } => {
+                self.videos = videos;
+                self.started = Some(now);
+                self.sample = Some((now, 0));
+            }
+            DownEvent::Part { bvid, cid, state } => {
+                match &state {
+                    PartState::Resolving if cid != 0 => {
+                        self.current = self
+                            .index
+                            .get(&(bvid.clone(), cid))
+                            .map(|&i| self.rows[i].title.clone())
+                            .unwrap_or_default();
+                        self.current_bytes = 0;
+                        self.current_total = None;
+                    }
+                    PartState::Downloading { bytes, total } => {
+                        let prev = self.part_bytes.insert(cid, *bytes).unwrap_or_default();
+                        self.bytes += bytes.saturating_sub(prev);
+                        self.current_bytes = *bytes;
+                        self.current_total = *total;
+                    }
+                    _ => {}
+                }
+                self.set_state(bvid, cid, state);
+            }
+            DownEvent::VideoDone { ok, .. } => {
+                self.done += 1;
+                self.current_bytes = 0;
+                self.current_total = None;
+                if !ok {
+                    self.failed += 1;
+                }
+            }
+        }
+        // 每秒更新一次速度，避免数字跳得太快
+        if let Some((t, b)) = self.sample {
+            let dt = now.duration_since(t).as_secs_f64();
+            if dt >= 1. {
+                self.speed = (self.bytes - b) as f64 / dt;
+                self.sample = Some((now, self.bytes));
+            }
+        }
+    }
+
+    /// 重试时保留队列表格，其余重新开始
+    fn restart(&mut self) {
+        *self = Progress {
+            rows: std::mem::take(&mut self.rows),
+            index: std::mem::take(&mut self.index),
+            ..Progress::default()
+        };
+    }
+
+    fn set_state(&mut self, bvid: String, cid: i64, state: PartState) {
+        if let Some(&i) = self.index.get(&(bvid.clone(), cid)) {
+            let row = &mut self.rows[i];
+            if let PartState::Queued { title } = &state {
+                row.title = title.clone();
+            }
+            row.state = state;
+            return;
+        }
+        let last = self.rows.iter().rposition(|r| r.bvid == bvid);
+        match (&state, last) {
+            // 重试时视频已经展开成分P，整个视频失败时标记还没完成的分P
+            (PartState::Failed { .. } | PartState::Cancelled, Some(_)) if cid == 0 => {
+                for row in self.rows.iter_mut().filter(|r| r.bvid == bvid) {
+                    if row.is_pending() {
+                        row.state = state.clone();
+                    }
+                }
+            }
+            (PartState::Queued { title }, None) => {
+                self.rows.push(PartRow::new(bvid, cid, title.clone()));
+                self.reindex();
+            }
+            // 取得分P列表后，换掉视频占的那一行
+            (PartState::Queued { title }, Some(i)) if cid != 0 => {
+                let row = PartRow::new(bvid, cid, title.clone());
+                if self.rows[i].cid == 0 {
+                    self.rows[i] = row;
+                } else {
+                    self.rows.insert(i + 1, row);
+                }
+                self.reindex();
+            }
+            _ => {}
+        }
+    }
+
+    fn reindex(&mut self) {
+        self.index = self
+            .rows
+            .iter()
+            .enumerate()
+            .map(|(i, r)| ((r.bvid.clone(), r.cid), i))
+            .collect();
+    }
+
+    /// 队列表格，下载中（`control`不为None）可以暂停、取消，下载完才能重试
+    fn view(&mut self, control: Option<&Control>) -> Element<Message> {
+        let rows = self
+            .rows
+            .iter_mut()
+            .fold(Column::new().spacing(5), |col, r| {
+                let mut row = Row::new()
+                    .spacing(10)
+                    .push(Text::new(&r.title).size(16).width(Length::FillPortion(3)))
+                    .push(
+                        Text::new(r.state.to_string())
+                            .size(16)
+                            .color(r.color())
+                            .width(Length::FillPortion(2)),
+                    );
+                if let PartState::Failed { .. } | PartState::Cancelled = r.state {
+                    let mut retry = Button::new(&mut r.retry_button, Text::new("重试").size(16));
+                    if control.is_none() {
+                        retry = retry.on_press(Message::Retry(r.bvid.clone(), r.cid));
+                    }
+                    row = row.push(retry);
+                }
+                if let (Some(control), true) = (control, r.is_pending()) {
+                    let bvid = Some(r.bvid.clone());
+                    let pause = if control.is_item_paused(&r.bvid) {
+                        Button::new(&mut r.pause_button, Text::new("继续").size(16))
+                            .on_press(Message::Resume(bvid.clone()))
+                    } else {
+                        Button::new(&mut r.pause_button, Text::new("暂停").size(16))
+                            .on_press(Message::Pause(bvid.clone()))
+                    };
+                    row = row.push(pause).push(
+                        Button::new(&mut r.cancel_button, Text::new("取消").size(16))
+                            .on_press(Message::Cancel(bvid)),
+                    );
+                }
+                if let PartState::Done { path } = &r.state {
+                    row = row.push(
+                        Button::new(&mut r.open_button, Text::new("打开位置").size(16))
+                            .on_press(Message::OpenLocation(path.clone())),
+                    );
+                }
+                col.push(row)
+            });
+        Scrollable::new(&mut self.scroll)
+            .max_height(300)
+            .push(rows)
+            .into()
+    }
+
+    /// 已完成的视频数，加上正在下载的文件的比例
+    fn finished(&self) -> f64 {
+        let current = match self.current_total {
+            Some(t) if t > 0 && self.done < self.videos => self.current_bytes as f64 / t as f64,
+            _ => 0.,
+        };
+        self.done as f64 + current
+    }
+
+    /// 按已经用掉的时间估计剩余时间
+    fn eta(&self) -> Option<u64> {
+        let finished = self.finished();
+        let elapsed = self.started?.elapsed().as_secs_f64();
+        if finished <= 0. {
+            return None;
+        }
+        Some((elapsed / finished * (self.videos as f64 - finished)).max(0.) as u64)
+    }
+}
+
+impl std::fmt::Display for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.started.is_none() {
+            return Ok(());
+        }
+        let percent = if self.videos > 0 {
+            100. * self.finished() / self.videos as f64
+        } else {
+            100.
+        };
+        write!(f, "进度：{}/{}（{:.1}%）", self.done, self.videos, percent)?;
+        if self.failed > 0 {
+            write!(f, "，失败{}个", self.failed)?;
+        }
+        if self.done < self.videos {
+            write!(f, "\n当前：{}", self.current)?;
+            if let Some(t) = self.current_total {
+                write!(
+                    f,
+                    "（{}/{}）",
+                    format_bytes(self.current_bytes),
+                    format_bytes(t)
+                )?;
             }
+            write!(f, "\n速度：{}/s", format_bytes(self.speed as u64))?;
+            if let Some(eta) = self.eta() {
+                write!(f, "，剩余约{}", format_duration(eta as i64))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 队列表格中的一行
+struct PartRow {
+    bvid: String,
+    cid: i64,
+    title: String,
+    state: PartState,
+    retry_button: button::State,
+    open_button: button::State,
+    pause_button: button::State,
+    cancel_button: button::State,
+}
+
+impl PartRow {
+    fn new(bvid: String, cid: i64, title: String) -> Self {
+        PartRow {
+            bvid,
+            cid,
+            state: PartState::Queued {
+                title: title.clone(),
+            },
+            title,
+            retry_button: button::State::new(),
+            open_button: button::State::new(),
+            pause_button: button::State::new(),
+            cancel_button: button::State::new(),
         }
     }
 
-    fn view(&mut self) -> Element<CheckMessage> {
-        Checkbox::new(self.v, &self.title, CheckMessage::Check).into()
+    /// 还没有结果（完成、跳过、失败或取消）
+    fn is_pending(&self) -> bool {
+        !matches!(
+            self.state,
+            PartState::Done { .. }
+                | PartState::Skipped { .. }
+                | PartState::Failed { .. }
+                | PartState::Cancelled
+        )
+    }
+
+    fn color(&self) -> Color {
+        match self.state {
+            PartState::Done { .. } => Color::from_rgb(0.2, 0.6, 0.2),
+            PartState::Skipped { .. } | PartState::Cancelled => Color::from_rgb(0.6, 0.6, 0.6),
+            PartState::Failed { .. } => Color::from_rgb(0.8, 0.2, 0.2),
+            _ => Color::BLACK,
+        }
+    }
+}
+
+/// 在文件管理器中显示文件
+fn open_location(path: &std::path::Path) -> std::io::Result<()> {
+    let mut cmd = if cfg!(target_os = "windows") {
+        let mut cmd = std::process::Command::new("explorer");
+        cmd.arg(format!("/select,{}", path.display()));
+        cmd
+    } else if cfg!(target_os = "macos") {
+        let mut cmd = std::process::Command::new("open");
+        cmd.arg("-R").arg(path);
+        cmd
+    } else {
+        let mut cmd = std::process::Command::new("xdg-open");
+        cmd.arg(path.parent().unwrap_or(path));
+        cmd
+    };
+    cmd.spawn().map(|_| ())
+}
+
+fn format_bytes(n: u64) -> String {
+    if n >= 1 << 20 {
+        format!("{:.1} MB", n as f64 / (1 << 20) as f64)
+    } else {
+        format!("{:.0} KB", n as f64 / 1024.)
     }
 }
 
-async fn open_mu(m: &Arc<Mutex<f64>>) -> f64 {
-    let prog = *Arc::clone(m).lock().await;
-    prog
+/// 把下载引擎的事件接收端包装成iced的订阅
+struct DownEvents {
+    id: u64,
+    rx: Arc<Mutex<UnboundedReceiver<DownEvent>>>,
 }
 
-async fn get_video_list(fid: &str, sessdata: &str) -> Option<Vec<serde_json::Value>> {
-    let v_list = bapi::get_fav_list(fid, sessdata).await;
+impl<H: std::hash::Hasher, I> iced_native::subscription::Recipe<H, I> for DownEvents {
+    type Output = DownEvent;
+
+    fn hash(&self, state: &mut H) {
+        use std::hash::Hash;
+        std::any::TypeId::of::<Self>().hash(state);
+        self.id.hash(state);
+    }
+
+    fn stream(
+        self: Box<Self>,
+        _input: futures::stream::BoxStream<'static, I>,
+    ) -> futures::stream::BoxStream<'static, DownEvent> {
+        // 发送端在下载结束时关闭，订阅随之结束
+        futures::stream::unfold(self.rx, |rx| async move {
+            let e = rx.lock().await.recv().await;
+            e.map(|e| (e, rx))
+        })
+        .boxed()
+    }
+}
+
+async fn get_video_list(fid: String, cred: Arc<Credential>) -> Option<Vec<serde_json::Value>> {
+    let v_list = bapi::get_fav_list(&fid, &cred).await;
     match v_list {
         Ok(v) => Some(v),
         Err(e) => {
-            println!("{:?}", e);
+            eprintln!("{:?}", e);
             None
         }
     }
 }
 
-async fn add_prog(prog: Arc<Mutex<f64>>) {
-    let mut prog = prog.lock().await;
-    *prog += 1.;
+async fn refresh_credential(cred: Arc<Credential>) -> Result<Option<Credential>, String> {
+    bapi::refresh_credential(&cred)
+        .await
+        .map_err(|e| e.to_string())
 }
 
-async fn start_download(
-    v_list: &Vec<serde_json::Value>,
-    sessdata: &str,
-    path: &str,
-    prog: Arc<Mutex<f64>>,
-) -> bool {
-    println!("共{}项", v_list.len());
-    let mut cnt1: i32 = 0;
-    for e in v_list.iter() {
-        cnt1 += 1;
-        println!("第{}个视频", cnt1);
-        let bvid = e["bvid"].as_str().unwrap_or_default();
-        let video_inf: bapi::VideoInf = bapi::VideoInf {
-            author: e["upper"]["name"]
-                .as_str()
-                .unwrap_or_default()
-                .parse()
-                .unwrap_or_default(),
-            name: e["title"]
+async fn get_account(cred: Arc<Credential>) -> Result<(i64, String), Option<String>> {
+    match bapi::get_nav(&cred).await {
+        Ok(nav) => Ok((
+            nav["data"]["mid"].as_i64().unwrap_or_default(),
+            nav["data"]["uname"]
                 .as_str()
                 .unwrap_or_default()
-                .parse()
-                .unwrap_or_default(),
-        };
-        let ps = bapi::get_ps(bvid, sessdata).await;
-        match ps {
-            Ok(ps) => {
-                let ps = ps["data"].as_array();
-                match ps {
-                    Some(ps) => {
-                        let mut cnt2 = 0;
-                        for p in ps.iter() {
-                            cnt2 += 1;
-                            println!("第{}P", cnt2);
-                            let cid = format!("{}", p["cid"].as_i64().unwrap_or_default());
-                            let p_name = p["part"].as_str().unwrap_or_default();
-                            let mut file_name =
-                                format!("{} - {} - {}", video_inf.name, p_name, video_inf.author);
-                            file_name = file_name.replace("\\", " ");
-                            file_name = file_name.replace("/", " ");
-                            file_name = file_name.replace("?", " ");
-                            file_name = file_name.replace("*", " ");
-                            file_name = file_name.replace(">", " ");
-                            file_name = file_name.replace("<", " ");
-                            file_name = file_name.replace("|", " ");
-                            file_name = file_name.replace(":", " ");
-                            println!("{}", file_name);
-                            match bapi::get_url(bvid, cid.as_str(), sessdata).await {
-                                Ok(u) => match u["data"]["dash"]["audio"].as_array() {
-                                    Some(u) => {
-                                        let music_url =
-                                            u[0]["baseUrl"].as_str().unwrap_or_default();
-                                        match bapi::download_music(
-                                            &format!("{}/{}.aac", path, file_name),
-                                            music_url,
-                                            sessdata,
-                                        )
-                                        .await
-                                        {
-                                            Ok(_) => {
-                                            }
-                                            Err(e) => {
-                                                println!("{:?}", e);
-                                            }
-                                        }
-                                    }
-                                    None => {}
-                                },
-                                Err(e) => {
-                                    println!("{:?}", e);
-                                }
-                            }
-                        }
-                    }
-                    None => {}
-                }
-            }
-            Err(e) => {
-                println!("{:?}", e);
-            }
+                .to_string(),
+        )),
+        Err(e) if e.is_not_login() => Err(None),
+        Err(e) => {
+            eprintln!("{:?}", e);
+            Err(Some(e.to_string()))
+        }
+    }
+}
+
+async fn load_cover(url: String, limit: Arc<Semaphore>) -> Option<std::path::PathBuf> {
+    let _permit = limit.acquire().await.ok()?;
+    match cover::get_cover(&url).await {
+        Ok(p) => Some(p),
+        Err(e) => {
+            eprintln!("{}", e);
+            None
+        }
+    }
+}
+
+async fn get_parts(bvid: String, cred: Arc<Credential>) -> Option<Vec<serde_json::Value>> {
+    match bapi::get_ps(&bvid, &cred).await {
+        Ok(ps) => ps["data"].as_array().cloned(),
+        Err(e) => {
+            eprintln!("{:?}", e);
+            None
         }
-        add_prog(Arc::clone(&prog)).await;
-        println!("---------");
     }
-    return true;
+}
+
+/// 输入凭据库口令的一行，凭据库不存在时是设置新口令
+fn passphrase_row<'a>(
+    input: &'a mut text_input::State,
+    value: &str,
+    button: &'a mut button::State,
+) -> Row<'a, Message> {
+    let (placeholder, label) = if Vault::exists() {
+        ("输入口令解锁保存的账号", "解锁")
+    } else {
+        ("设置口令，用来加密保存账号的Cookie", "设置")
+    };
+    Row::new()
+        .spacing(10)
+        .push(TextInput::new(input, placeholder, value, Message::PassphraseChanged).password())
+        .push(Button::new(button, Text::new(label)).on_press(Message::Unlock))
+}
+
+/// Firefox的`cookies.sqlite`、Chromium的`Cookies`或cookies.txt，按内容判断格式
+async fn choose_cookie_file() -> Option<std::path::PathBuf> {
+    let r = rfd::AsyncFileDialog::new().pick_file().await;
+    r.map(|f| f.path().to_path_buf())
+}
+
+async fn choose_export_file() -> Option<std::path::PathBuf> {
+    let r = rfd::AsyncFileDialog::new()
+        .add_filter("cookies.txt", &["txt"])
+        .save_file()
+        .await;
+    r.map(|f| f.path().to_path_buf())
 }
 
 async fn choose_file() -> String {
@@ -0,0 +1,358 @@
+use crate::filter;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// 记录在输出目录中的清单文件名
+pub const MANIFEST_NAME: &str = ".bili_music_download.json";
+/// 归档已移出收藏夹的文件时使用的子目录
+pub const REMOVED_DIR: &str = "_removed";
+
+/// 一个已下载的分P
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub bvid: String,
+    pub cid: i64,
+    /// 相对于输出目录的路径
+    pub path: String,
+    pub size: u64,
+    pub quality: i64,
+    /// 下载完成的时间（Unix时间戳，秒）
+    pub timestamp: u64,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub part: String,
+    #[serde(default)]
+    pub author: String,
+    /// 下载时所在的收藏夹，旧版本的清单中没有
+    #[serde(default)]
+    pub fav_id: String,
+    /// 下载时用的账号名
+    #[serde(default)]
+    pub account: String,
+}
+
+/// 收藏夹中已删除的视频对应的本地文件如何处理
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MirrorAction {
+    Report,
+    Archive,
+    Delete,
+}
+
+/// 收藏夹中已失效（被删除或下架）的视频
+#[derive(Clone, Debug)]
+pub struct Invalid {
+    pub bvid: String,
+    /// 在收藏夹列表中的下标
+    pub index: usize,
+    pub fav_time: i64,
+    /// 以前下载过的分P，可以从中知道原来的标题，没下载过时为空
+    pub local: Vec<Entry>,
+}
+
+/// 每个输出目录一份的下载清单，以bvid+cid为键
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(skip)]
+    dir: PathBuf,
+    entries: HashMap<String, Entry>,
+    /// 正在下载、还没有写入清单的路径，值为bvid:cid
+    #[serde(skip)]
+    claimed: HashMap<String, String>,
+}
+
+impl Manifest {
+    /// 读取目录中的清单，不存在时返回空清单。
+    /// 清单损坏时改名保留下来再返回空清单，之后保存时不会覆盖掉原来的记录
+    pub fn load(dir: &str) -> Manifest {
+        let dir = PathBuf::from(dir);
+        let path = dir.join(MANIFEST_NAME);
+        let mut manifest: Manifest = match std::fs::read(&path) {
+            Ok(s) => match serde_json::from_slice(&s) {
+                Ok(m) => m,
+                Err(e) => {
+                    let bad = dir.join(format!("{}.{}.bad", MANIFEST_NAME, now()));
+                    match std::fs::rename(&path, &bad) {
+                        Ok(_) => eprintln!(
+                            "清单{}已损坏（{}），已改名为{}",
+                            path.display(),
+                            e,
+                            bad.display()
+                        ),
+                        Err(err) => {
+                            eprintln!("清单{}已损坏（{}），改名失败：{}", path.display(), e, err)
+                        }
+                    }
+                    Manifest::default()
+                }
+            },
+            Err(_) => Manifest::default(),
+        };
+        manifest.dir = dir;
+        manifest
+    }
+
+    /// 先写临时文件再改名，中途退出不会把清单写坏
+    pub fn save(&self) -> std::io::Result<()> {
+        let tmp = self.dir.join(format!("{}.tmp", MANIFEST_NAME));
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, self.dir.join(MANIFEST_NAME))
+    }
+
+    pub fn key(bvid: &str, cid: i64) -> String {
+        format!("{}:{}", bvid, cid)
+    }
+
+    pub fn get(&self, bvid: &str, cid: i64) -> Option<&Entry> {
+        self.entries.get(&Manifest::key(bvid, cid))
+    }
+
+    /// 清单中有记录，且文件还在、大小没变
+    pub fn is_intact(&self, bvid: &str, cid: i64) -> bool {
+        match self.get(bvid, cid) {
+            Some(e) => match std::fs::metadata(self.full_path(&e.path)) {
+                Ok(m) => m.is_file() && m.len() == e.size,
+                Err(_) => false,
+            },
+            None => false,
+        }
+    }
+
+    pub fn insert(&mut self, entry: Entry) {
+        let key = Manifest::key(&entry.bvid, entry.cid);
+        self.claimed.retain(|_, k| *k != key);
+        self.entries.insert(key, entry);
+    }
+
+    /// 为`bvid:cid`选一个不和其它条目冲突的路径：`base.ext`、`base (2).ext`……
+    /// 选中的路径在写入清单前也算被占用，同时下载的分P不会选到同一个文件；
+    /// 不在清单中的已有文件也算被占用，不会被覆盖。
+    /// 已经有记录、且原来的路径就是其中之一时沿用原来的路径
+    pub fn claim_path(
+        &mut self,
+        bvid: &str,
+        cid: i64,
+        base: &str,
+        ext: &str,
+        ignore_case: bool,
+    ) -> String {
+        let key = Manifest::key(bvid, cid);
+        let norm = |p: &str| {
+            if ignore_case {
+                p.to_lowercase()
+            } else {
+                p.to_string()
+            }
+        };
+        let mut taken: HashMap<String, &str> = self
+            .entries
+            .iter()
+            .map(|(k, e)| (norm(&e.path), k.as_str()))
+            .collect();
+        for (p, k) in self.claimed.iter() {
+            taken.insert(p.clone(), k.as_str());
+        }
+        let candidate = |n: usize| {
+            if n == 1 {
+                format!("{}.{}", base, ext)
+            } else {
+                format!("{} ({}).{}", base, n, ext)
+            }
+        };
+        let old = self.get(bvid, cid).map(|e| e.path.clone()).filter(|p| {
+            taken.get(&norm(p)) == Some(&key.as_str())
+                && is_numbered(&norm(p), &norm(base), &norm(ext))
+        });
+        let path = match old {
+            Some(path) => path,
+            None => {
+                let mut n = 1;
+                loop {
+                    let path = candidate(n);
+                    let free = match taken.get(&norm(&path)) {
+                        Some(k) => *k == key,
+                        None => !self.full_path(&path).exists(),
+                    };
+                    if free {
+                        break path;
+                    }
+                    n += 1;
+                }
+            }
+        };
+        self.claimed.insert(norm(&path), key);
+        path
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.values()
+    }
+
+    pub fn full_path(&self, path: &str) -> PathBuf {
+        self.dir.join(Path::new(path))
+    }
+
+    /// 属于收藏夹`fid`，但已经不在`list`（`get_fav_list`的结果）中的条目
+    pub fn removed(&self, fid: &str, list: &[serde_json::Value]) -> Vec<Entry> {
+        let current: HashSet<&str> = list.iter().filter_map(|v| v["bvid"].as_str()).collect();
+        let mut res: Vec<Entry> = self
+            .entries()
+            .filter(|e| e.fav_id == fid && !current.contains(e.bvid.as_str()))
+            .cloned()
+            .collect();
+        res.sort_by(|a, b| a.path.cmp(&b.path));
+        res
+    }
+
+    /// `list`中已失效的视频，带上清单中以前下载时的记录
+    pub fn invalid(&self, list: &[serde_json::Value]) -> Vec<Invalid> {
+        list.iter()
+            .enumerate()
+            .filter(|(_, v)| filter::is_invalid(v))
+            .map(|(index, v)| {
+                let bvid = v["bvid"].as_str().unwrap_or_default();
+                let mut local: Vec<Entry> =
+                    self.entries().filter(|e| e.bvid == bvid).cloned().collect();
+                local.sort_by_key(|e| e.path.clone());
+                Invalid {
+                    bvid: bvid.to_string(),
+                    index,
+                    fav_time: filter::fav_time(v),
+                    local,
+                }
+            })
+            .collect()
+    }
+
+    /// 对已移出收藏夹的条目执行`action`，`dry_run`时只返回会处理哪些条目。
+    /// 处理成功的条目会从清单中去掉，单个文件失败不影响其它文件
+    pub fn mirror(
+        &mut self,
+        fid: &str,
+        list: &[serde_json::Value],
+        action: MirrorAction,
+        dry_run: bool,
+    ) -> Vec<(Entry, std::io::Result<()>)> {
+        let removed = self.removed(fid, list);
+        if dry_run || action == MirrorAction::Report {
+            return removed.into_iter().map(|e| (e, Ok(()))).collect();
+        }
+        let mut res = vec![];
+        for e in removed {
+            let from = self.full_path(&e.path);
+            let r = match action {
+                MirrorAction::Archive => {
+                    let to = self.dir.join(REMOVED_DIR).join(Path::new(&e.path));
+                    to.parent()
+                        .map_or(Ok(()), std::fs::create_dir_all)
+                        .and_then(|_| std::fs::rename(&from, &to))
+                }
+                _ => std::fs::remove_file(&from),
+            };
+            // 文件已经不在了也算处理完
+            let r = match r {
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                r => r,
+            };
+            if r.is_ok() {
+                self.entries.remove(&Manifest::key(&e.bvid, e.cid));
+            }
+            res.push((e, r));
+        }
+        if let Err(e) = self.save() {
+            eprintln!("清单写入失败：{}", e);
+        }
+        res
+    }
+}
+
+/// `path`是否为`base.ext`或`base (n).ext`
+fn is_numbered(path: &str, base: &str, ext: &str) -> bool {
+    let rest = match path
+        .strip_prefix(base)
+        .and_then(|r| r.strip_suffix(ext))
+        .and_then(|r| r.strip_suffix('.'))
+    {
+        Some(r) => r,
+        None => return false,
+    };
+    rest.is_empty()
+        || rest
+            .strip_prefix(" (")
+            .and_then(|r| r.strip_suffix(')'))
+            .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(bvid: &str, cid: i64, path: &str) -> Entry {
+        Entry {
+            bvid: bvid.to_string(),
+            cid,
+            path: path.to_string(),
+            size: 0,
+            quality: 0,
+            timestamp: 0,
+            title: String::new(),
+            part: String::new(),
+            author: String::new(),
+            fav_id: String::new(),
+            account: String::new(),
+        }
+    }
+
+    fn load(dir: &tempfile::TempDir) -> Manifest {
+        Manifest::load(dir.path().to_str().unwrap())
+    }
+
+    #[test]
+    fn claim_path_avoids_other_entries_and_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut m = load(&dir);
+        m.insert(entry("BV1", 1, "歌.mp3"));
+        std::fs::write(m.full_path("歌 (2).mp3"), b"").unwrap();
+        assert_eq!(m.claim_path("BV2", 2, "歌", "mp3", false), "歌 (3).mp3");
+        assert_eq!(m.claim_path("BV3", 3, "歌", "mp3", false), "歌 (4).mp3");
+        assert_eq!(m.claim_path("BV1", 1, "歌", "mp3", false), "歌.mp3");
+        // 清单中的路径不区分大小写比较
+        assert_eq!(
+            m.claim_path("BV4", 4, "歌 (3)", "MP3", true),
+            "歌 (3) (2).MP3"
+        );
+    }
+
+    #[test]
+    fn claim_path_keeps_the_recorded_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut m = load(&dir);
+        m.insert(entry("BV1", 1, "歌 (3).mp3"));
+        assert_eq!(m.claim_path("BV1", 1, "歌", "mp3", false), "歌 (3).mp3");
+        // 模板变了时用新的名字
+        assert_eq!(m.claim_path("BV1", 1, "别的", "mp3", false), "别的.mp3");
+    }
+
+    #[test]
+    fn corrupt_manifest_is_kept_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(MANIFEST_NAME), b"{not json").unwrap();
+        assert_eq!(load(&dir).entries().count(), 0);
+        assert!(!dir.path().join(MANIFEST_NAME).exists());
+        let kept = std::fs::read_dir(dir.path())
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().ends_with(".bad"))
+            .count();
+        assert_eq!(kept, 1);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::dirs::data_dir;
+use crate::download::{DownEvent, DownOptions, PartState};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+
+/// 下载队列的记录文件名，在数据目录中
+pub const JOURNAL_NAME: &str = "queue.json";
+
+/// 记录下载队列和每个分P的状态，程序退出后可以继续没完成的下载
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Journal {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    /// 开始下载时的选项，没有时表示没有队列
+    opts: Option<DownOptions>,
+    videos: Vec<serde_json::Value>,
+    /// bvid→cid→状态，cid为0时是还没取得分P列表的整个视频
+    states: HashMap<String, BTreeMap<i64, PartState>>,
+}
+
+impl Journal {
+    pub fn load() -> Journal {
+        let path = data_dir().map(|d| d.join(JOURNAL_NAME));
+        let mut journal: Journal = path
+            .as_ref()
+            .and_then(|p| std::fs::read(p).ok())
+            .and_then(|s| serde_json::from_slice(&s).ok())
+            .unwrap_or_default();
+        journal.path = path;
+        journal
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "找不到数据目录"))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, path)
+    }
+
+    /// 开始新的队列，替换原来的记录
+    pub fn start(
+        &mut self,
+        opts: &DownOptions,
+        videos: &[serde_json::Value],
+    ) -> std::io::Result<()> {
+        self.opts = Some(opts.clone());
+        self.videos = videos.to_vec();
+        self.states.clear();
+        self.save()
+    }
+
+    /// 放弃队列，删除记录文件
+    pub fn clear(&mut self) -> std::io::Result<()> {
+        self.opts = None;
+        self.videos.clear();
+        self.states.clear();
+        match &self.path {
+            Some(path) if path.exists() => std::fs::remove_file(path),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn opts(&self) -> Option<&DownOptions> {
+        self.opts.as_ref()
+    }
+
+    /// 按下载事件更新状态。下载进度变化太频繁，只在其它状态变化时写入文件
+    pub fn record(&mut self, e: &DownEvent) -> std::io::Result<()> {
+        let (bvid, cid, state) = match e {
+            DownEvent::Part { bvid, cid, state } => (bvid, *cid, state),
+            _ => return Ok(()),
+        };
+        if self.opts.is_none() {
+            return Ok(());
+        }
+        let parts = self.states.entry(bvid.clone()).or_default();
+        let expanded = parts.keys().any(|&c| c != 0);
+        match state {
+            // 已经取得分P列表时，整个视频失败或取消只影响还没完成的分P
+            PartState::Failed { .. } | PartState::Cancelled if cid == 0 && expanded => {
+                for s in parts.values_mut().filter(|s| !is_finished(s)) {
+                    *s = state.clone();
+                }
+            }
+            _ if cid == 0 && expanded => return Ok(()),
+            _ => {
+                if cid != 0 {
+                    parts.remove(&0);
+                }
+                parts.insert(cid, state.clone());
+            }
+        }
+        if let PartState::Downloading { .. } = state {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    /// 还没完成的视频数，包括失败和取消的
+    pub fn pending(&self) -> usize {
+        if self.opts.is_none() {
+            return 0;
+        }
+        self.videos
+            .iter()
+            .filter(|v| self.unfinished(v).is_some())
+            .count()
+    }
+
+    /// 要继续下载的视频和分P，可以直接给`start_download`和`DownOptions::parts`用。
+    /// 跳过已完成的，失败和取消的重新下载
+    pub fn remaining(&self) -> (Vec<serde_json::Value>, HashMap<String, HashSet<i64>>) {
+        let mut parts = self
+            .opts
+            .as_ref()
+            .map(|o| o.parts.clone())
+            .unwrap_or_default();
+        let mut videos = vec![];
+        for v in &self.videos {
+            if let Some(cids) = self.unfinished(v) {
+                if !cids.is_empty() {
+                    parts.insert(crate::filter::bvid(v).to_string(), cids);
+                }
+                videos.push(v.clone());
+            }
+        }
+        (videos, parts)
+    }
+
+    /// 视频没完成时返回没完成的分P，还没取得分P列表时为空
+    fn unfinished(&self, v: &serde_json::Value) -> Option<HashSet<i64>> {
+        let parts = match self.states.get(crate::filter::bvid(v)) {
+            Some(parts) if !parts.is_empty() => parts,
+            _ => return Some(HashSet::new()),
+        };
+        let cids: HashSet<i64> = parts
+            .iter()
+            .filter(|(_, s)| !is_finished(s))
+            .map(|(&c, _)| c)
+            .collect();
+        if cids.is_empty() {
+            None
+        } else if cids.contains(&0) {
+            Some(HashSet::new())
+        } else {
+            Some(cids)
+        }
+    }
+}
+
+fn is_finished(state: &PartState) -> bool {
+    matches!(state, PartState::Done { .. } | PartState::Skipped { .. })
+}
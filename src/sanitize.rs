@@ -0,0 +1,221 @@
+use serde::{Deserialize, Serialize};
+
+/// 文件名要兼容的文件系统
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Strictness {
+    /// Linux、macOS：只去掉`/`和控制字符
+    Posix,
+    /// Windows：还要去掉`\ : * ? " < > |`、结尾的点和空格以及CON、NUL等保留名
+    #[default]
+    Windows,
+    /// U盘常用的FAT32/exFAT：在Windows的基础上按UTF-16计算长度
+    Fat32,
+}
+
+impl Strictness {
+    pub const ALL: [Strictness; 3] = [Strictness::Posix, Strictness::Windows, Strictness::Fat32];
+
+    /// Windows和FAT32不区分大小写，比较文件名冲突时也不区分
+    pub fn ignore_case(self) -> bool {
+        self != Strictness::Posix
+    }
+}
+
+impl std::fmt::Display for Strictness {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Strictness::Posix => "posix",
+            Strictness::Windows => "windows",
+            Strictness::Fat32 => "fat32",
+        })
+    }
+}
+
+impl std::str::FromStr for Strictness {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Strictness, String> {
+        match s.to_lowercase().as_str() {
+            "posix" => Ok(Strictness::Posix),
+            "windows" => Ok(Strictness::Windows),
+            "fat32" | "exfat" => Ok(Strictness::Fat32),
+            _ => Err(format!("未知的文件系统：{}", s)),
+        }
+    }
+}
+
+/// 单个文件名最长255字节（FAT32为255个UTF-16字符）
+const MAX_NAME: usize = 255;
+/// 给冲突时加的“ (2)”之类后缀预留的长度
+const SUFFIX_RESERVE: usize = 8;
+
+const WINDOWS_RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// 处理模板中占位符的值：除了不允许的字符，路径分隔符也要去掉，
+/// 这样值本身不会产生子目录
+pub fn clean_value(s: &str, strictness: Strictness) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_control() || c == '/' || c == '\\' || forbidden(c, strictness) {
+                ' '
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+fn forbidden(c: char, strictness: Strictness) -> bool {
+    match strictness {
+        Strictness::Posix => c == '/' || c == '\0',
+        Strictness::Windows | Strictness::Fat32 => c.is_control() || "\\/:*?\"<>|".contains(c),
+    }
+}
+
+/// 处理路径中的一段（目录名或不含扩展名的文件名），`reserve`为要留给扩展名等的长度
+pub fn sanitize_component(s: &str, strictness: Strictness, reserve: usize) -> String {
+    let mut name: String = s
+        .chars()
+        .map(|c| {
+            if c.is_control() || forbidden(c, strictness) {
+                ' '
+            } else {
+                c
+            }
+        })
+        .collect();
+    name = name.trim().to_string();
+    if strictness != Strictness::Posix {
+        name = name.trim_end_matches(['.', ' ']).to_string();
+        let stem = name.split('.').next().unwrap_or_default().trim_end();
+        if WINDOWS_RESERVED
+            .iter()
+            .any(|r| r.eq_ignore_ascii_case(stem))
+        {
+            name.insert(stem.len(), '_');
+        }
+    }
+    name = truncate(&name, MAX_NAME.saturating_sub(reserve), strictness);
+    if strictness != Strictness::Posix {
+        name = name.trim_end_matches(['.', ' ']).to_string();
+    }
+    // `.`和`..`会变成当前目录或上级目录
+    if name.is_empty() || name.chars().all(|c| c == '.') {
+        name = String::from("_");
+    }
+    name
+}
+
+/// 处理模板渲染出的相对路径（以`/`分隔、不含扩展名），每段单独处理
+pub fn sanitize_path(path: &str, ext: &str, strictness: Strictness) -> String {
+    let parts: Vec<&str> = path.split('/').filter(|p| !p.trim().is_empty()).collect();
+    let last = parts.len().saturating_sub(1);
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let reserve = if i == last {
+                ext.len() + 1 + SUFFIX_RESERVE
+            } else {
+                0
+            };
+            sanitize_component(p, strictness, reserve)
+        })
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+/// 按字节（FAT32按UTF-16）截断，不会截断在字符中间
+fn truncate(s: &str, max: usize, strictness: Strictness) -> String {
+    let mut len = 0;
+    let mut out = String::new();
+    for c in s.chars() {
+        len += match strictness {
+            Strictness::Fat32 => c.len_utf16(),
+            _ => c.len_utf8(),
+        };
+        if len > max {
+            break;
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_never_create_directories() {
+        assert_eq!(clean_value("a/b\\c:d", Strictness::Windows), "a b c d");
+        assert_eq!(clean_value("a/b\\c:d", Strictness::Posix), "a b c:d");
+    }
+
+    #[test]
+    fn windows_names() {
+        let w = Strictness::Windows;
+        assert_eq!(sanitize_component("a:b?", w, 0), "a b");
+        assert_eq!(sanitize_component("name. . ", w, 0), "name");
+        assert_eq!(sanitize_component("CON", w, 0), "CON_");
+        assert_eq!(sanitize_component("con.txt", w, 0), "con_.txt");
+        assert_eq!(sanitize_component("CONSOLE", w, 0), "CONSOLE");
+        assert_eq!(sanitize_component("CON", Strictness::Posix, 0), "CON");
+        assert_eq!(sanitize_component("a:b?", Strictness::Posix, 0), "a:b?");
+    }
+
+    #[test]
+    fn empty_and_dot_names() {
+        for s in ["", "  ", ".", ".."] {
+            assert_eq!(sanitize_component(s, Strictness::Posix, 0), "_", "{:?}", s);
+            assert_eq!(
+                sanitize_component(s, Strictness::Windows, 0),
+                "_",
+                "{:?}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn truncates_on_char_boundaries() {
+        let han = "汉".repeat(100);
+        assert_eq!(
+            sanitize_component(&han, Strictness::Posix, 0)
+                .chars()
+                .count(),
+            85
+        );
+        assert_eq!(
+            sanitize_component(&han, Strictness::Fat32, 0)
+                .chars()
+                .count(),
+            100
+        );
+        let emoji = "😀".repeat(200);
+        assert_eq!(
+            sanitize_component(&emoji, Strictness::Fat32, 0)
+                .chars()
+                .count(),
+            127
+        );
+        // 截断后结尾的点也要去掉
+        let dots = format!("{}.{}", "a".repeat(254), "b");
+        assert_eq!(
+            sanitize_component(&dots, Strictness::Windows, 0),
+            "a".repeat(254)
+        );
+    }
+
+    #[test]
+    fn paths_reserve_room_for_the_extension() {
+        assert_eq!(sanitize_path("a/ /b", "mp3", Strictness::Posix), "a/b");
+        let long = "x".repeat(300);
+        let path = sanitize_path(&format!("{}/{}", long, long), "mp3", Strictness::Posix);
+        let parts: Vec<&str> = path.split('/').collect();
+        assert_eq!(parts[0].len(), MAX_NAME);
+        assert_eq!(parts[1].len(), MAX_NAME - 4 - SUFFIX_RESERVE);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::dirs::config_dir;
+use crate::filter::bvid;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+/// 保存选择预设的文件名，在配置目录中
+pub const PRESETS_NAME: &str = "presets.json";
+
+/// 列表中选中了哪些视频和分P，以bvid为键，收藏夹重新排序后仍然有效。
+///
+/// 记录的是没选的视频，所以之后新收藏的视频默认是选中的
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Selection {
+    unselected: HashSet<String>,
+    /// 只下载部分分P的视频，值为选中的cid；不在其中的视频下载全部分P
+    parts: HashMap<String, HashSet<i64>>,
+}
+
+impl Selection {
+    pub fn is_selected(&self, bvid: &str) -> bool {
+        !self.unselected.contains(bvid)
+    }
+
+    pub fn set(&mut self, bvid: &str, selected: bool) {
+        if selected {
+            self.unselected.remove(bvid);
+        } else {
+            self.unselected.insert(bvid.to_string());
+        }
+    }
+
+    pub fn select_all(&mut self, list: &[serde_json::Value]) {
+        for v in list {
+            self.unselected.remove(bvid(v));
+        }
+    }
+
+    pub fn clear(&mut self, list: &[serde_json::Value]) {
+        self.unselected.extend(list.iter().map(|v| bvid(v).to_string()));
+    }
+
+    pub fn invert(&mut self, list: &[serde_json::Value]) {
+        for v in list {
+            let b = bvid(v);
+            let selected = self.is_selected(b);
+            self.set(b, !selected);
+        }
+    }
+
+    pub fn is_part_selected(&self, bvid: &str, cid: i64) -> bool {
+        self.parts.get(bvid).map_or(true, |p| p.contains(&cid))
+    }
+
+    /// `all`为这个视频的全部分P，全部选中时不再单独记录
+    pub fn set_part(&mut self, bvid: &str, cid: i64, selected: bool, all: &[i64]) {
+        let parts = self
+            .parts
+            .entry(bvid.to_string())
+            .or_insert_with(|| all.iter().cloned().collect());
+        if selected {
+            parts.insert(cid);
+        } else {
+            parts.remove(&cid);
+        }
+        if all.iter().all(|c| parts.contains(c)) {
+            self.parts.remove(bvid);
+        }
+    }
+
+    /// 按列表原来的顺序返回选中的视频
+    pub fn filter(&self, list: &[serde_json::Value]) -> Vec<serde_json::Value> {
+        list.iter()
+            .filter(|v| self.is_selected(bvid(v)))
+            .cloned()
+            .collect()
+    }
+
+    /// 给`DownOptions::parts`用
+    pub fn parts(&self) -> &HashMap<String, HashSet<i64>> {
+        &self.parts
+    }
+}
+
+/// 每个收藏夹的命名选择预设
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Presets {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    presets: HashMap<String, HashMap<String, Selection>>,
+}
+
+impl Presets {
+    pub fn load() -> Presets {
+        let path = config_dir().map(|d| d.join(PRESETS_NAME));
+        let mut presets: Presets = path
+            .as_ref()
+            .and_then(|p| std::fs::read(p).ok())
+            .and_then(|s| serde_json::from_slice(&s).ok())
+            .unwrap_or_default();
+        presets.path = path;
+        presets
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let path = self.path.as_ref().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "找不到配置目录")
+        })?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, path)
+    }
+
+    /// 收藏夹的预设名，按名称排序
+    pub fn names(&self, fid: &str) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .presets
+            .get(fid)
+            .map(|p| p.keys().cloned().collect())
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+
+    pub fn get(&self, fid: &str, name: &str) -> Option<&Selection> {
+        self.presets.get(fid).and_then(|p| p.get(name))
+    }
+
+    pub fn insert(&mut self, fid: &str, name: &str, selection: Selection) {
+        self.presets
+            .entry(fid.to_string())
+            .or_default()
+            .insert(name.to_string(), selection);
+    }
+}
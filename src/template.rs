@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+
+/// 和以前版本一样的文件名
+pub const DEFAULT_TEMPLATE: &str = "{title} - {part_name} - {upper}";
+
+/// 模板中可用的占位符
+pub const PLACEHOLDERS: [&str; 14] = [
+    "title",
+    "part",
+    "part_name",
+    "part_index",
+    "part_count",
+    "upper",
+    "upper_mid",
+    "bvid",
+    "cid",
+    "fav_folder",
+    "fav_id",
+    "quality",
+    "pubdate",
+    "fav_time",
+];
+
+#[derive(Clone, Debug)]
+enum Item {
+    Text(String),
+    /// 占位符和冒号后的格式
+    Var(String, Option<String>),
+    /// `[...]`，其中任一占位符为空时整段省略
+    Group(Vec<Item>),
+}
+
+/// 文件名模板，如`{upper}/{title}[ - {part}]`。
+///
+/// `/`会生成子目录；`{pubdate:%Y}`这样的时间占位符可以带strftime风格的格式，
+/// 支持`%Y %m %d %H %M %S`，按北京时间；`{{`、`}}`、`[[`、`]]`是字面的括号。
+/// `{part}`在视频只有一个分P时为空，`{part_name}`总是分P名
+#[derive(Clone, Debug)]
+pub struct Template {
+    items: Vec<Item>,
+}
+
+/// 渲染模板用的值
+#[derive(Clone, Debug, Default)]
+pub struct Vars {
+    values: HashMap<&'static str, String>,
+    times: HashMap<&'static str, i64>,
+}
+
+impl Vars {
+    pub fn set(&mut self, name: &'static str, value: impl Into<String>) -> &mut Vars {
+        self.values.insert(name, value.into());
+        self
+    }
+
+    /// 设置Unix时间戳，为0时视为没有
+    pub fn set_time(&mut self, name: &'static str, timestamp: i64) -> &mut Vars {
+        if timestamp > 0 {
+            self.times.insert(name, timestamp);
+        }
+        self
+    }
+
+    /// 用`set`设置的值，没有时为空
+    pub fn value(&self, name: &str) -> &str {
+        self.values
+            .get(name)
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+
+    fn get(&self, name: &str, spec: Option<&str>) -> String {
+        if let Some(t) = self.times.get(name) {
+            return format_time(*t, spec.unwrap_or("%Y-%m-%d"));
+        }
+        self.values.get(name).cloned().unwrap_or_default()
+    }
+}
+
+impl Template {
+    pub fn parse(s: &str) -> Result<Template, String> {
+        let mut chars = s.chars().peekable();
+        let items = parse_items(&mut chars, false)?;
+        Ok(Template { items })
+    }
+
+    /// 渲染出相对于输出目录的路径（不含扩展名），以`/`分隔。
+    /// 占位符的值经过`clean`处理，不会产生额外的目录层级
+    pub fn render(&self, vars: &Vars, clean: impl Fn(&str) -> String) -> String {
+        let mut out = String::new();
+        render_items(&self.items, vars, &clean, &mut out);
+        out.split('/')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<&str>>()
+            .join("/")
+    }
+}
+
+fn parse_items(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    in_group: bool,
+) -> Result<Vec<Item>, String> {
+    let mut items = vec![];
+    let mut text = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '}' | '[' | ']' if chars.peek() == Some(&c) => {
+                chars.next();
+                text.push(c);
+            }
+            '{' => {
+                let mut var = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => var.push(c),
+                        None => return Err(String::from("模板中的{没有对应的}")),
+                    }
+                }
+                let (name, spec) = match var.find(':') {
+                    Some(i) => (var[..i].to_string(), Some(var[i + 1..].to_string())),
+                    None => (var, None),
+                };
+                if !PLACEHOLDERS.contains(&name.as_str()) {
+                    return Err(format!("未知的占位符：{{{}}}", name));
+                }
+                if !text.is_empty() {
+                    items.push(Item::Text(std::mem::take(&mut text)));
+                }
+                items.push(Item::Var(name, spec));
+            }
+            '[' => {
+                if !text.is_empty() {
+                    items.push(Item::Text(std::mem::take(&mut text)));
+                }
+                items.push(Item::Group(parse_items(chars, true)?));
+            }
+            ']' if in_group => {
+                if !text.is_empty() {
+                    items.push(Item::Text(text));
+                }
+                return Ok(items);
+            }
+            '}' | ']' => return Err(format!("模板中多余的{}", c)),
+            c => text.push(c),
+        }
+    }
+    if in_group {
+        return Err(String::from("模板中的[没有对应的]"));
+    }
+    if !text.is_empty() {
+        items.push(Item::Text(text));
+    }
+    Ok(items)
+}
+
+/// 返回false表示有占位符为空
+fn render_items(
+    items: &[Item],
+    vars: &Vars,
+    clean: &impl Fn(&str) -> String,
+    out: &mut String,
+) -> bool {
+    let mut complete = true;
+    for item in items {
+        match item {
+            Item::Text(t) => out.push_str(t),
+            Item::Var(name, spec) => {
+                let v = clean(&vars.get(name, spec.as_deref()));
+                complete &= !v.trim().is_empty();
+                out.push_str(&v);
+            }
+            Item::Group(g) => {
+                let mut s = String::new();
+                if render_items(g, vars, clean, &mut s) {
+                    out.push_str(&s);
+                }
+            }
+        }
+    }
+    complete
+}
+
+/// 解析北京时间的`2020`、`2020-05`或`2020-05-01`，返回这段时间开始和结束的Unix时间戳。
+/// 年份只支持1到9999，更大的数在计算中会溢出
+pub fn parse_date(s: &str) -> Option<(i64, i64)> {
+    let nums = s
+        .trim()
+        .split('-')
+        .map(|n| n.trim().parse::<i64>().ok())
+        .collect::<Option<Vec<i64>>>()?;
+    if !nums.first().is_some_and(|y| (1..=9999).contains(y)) {
+        return None;
+    }
+    let (start, end) = match nums[..] {
+        [y] => (days_from_civil(y, 1, 1), days_from_civil(y + 1, 1, 1)),
+        [y, m] if (1..=12).contains(&m) => (
+            days_from_civil(y, m, 1),
+            days_from_civil(y + m / 12, m % 12 + 1, 1),
+        ),
+        [y, m, d] if (1..=12).contains(&m) && (1..=days_in_month(y, m)).contains(&d) => {
+            let day = days_from_civil(y, m, d);
+            (day, day + 1)
+        }
+        _ => return None,
+    };
+    Some((start * 86400 - 8 * 3600, end * 86400 - 8 * 3600 - 1))
+}
+
+pub(crate) fn days_in_month(y: i64, m: i64) -> i64 {
+    days_from_civil(y + m / 12, m % 12 + 1, 1) - days_from_civil(y, m, 1)
+}
+
+/// 公历日期到1970-01-01的天数，`format_time`中推算的逆运算
+pub(crate) fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (m + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// 按北京时间格式化Unix时间戳
+pub fn format_time(timestamp: i64, fmt: &str) -> String {
+    let t = timestamp + 8 * 3600;
+    let (days, secs) = (t.div_euclid(86400), t.rem_euclid(86400));
+    // 由天数推算公历日期，见 http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+
+    let mut out = String::new();
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", y)),
+            Some('m') => out.push_str(&format!("{:02}", m)),
+            Some('d') => out.push_str(&format!("{:02}", d)),
+            Some('H') => out.push_str(&format!("{:02}", secs / 3600)),
+            Some('M') => out.push_str(&format!("{:02}", secs % 3600 / 60)),
+            Some('S') => out.push_str(&format!("{:02}", secs % 60)),
+            Some(c) => out.push(c),
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, vars: &Vars) -> String {
+        Template::parse(template)
+            .unwrap()
+            .render(vars, |s| s.replace('/', "_"))
+    }
+
+    #[test]
+    fn groups_are_dropped_when_a_placeholder_is_empty() {
+        let mut vars = Vars::default();
+        vars.set("title", "歌").set("upper", "UP");
+        assert_eq!(render("{upper}/{title}[ - {part}]", &vars), "UP/歌");
+        vars.set("part", "P2");
+        assert_eq!(render("{upper}/{title}[ - {part}]", &vars), "UP/歌 - P2");
+    }
+
+    #[test]
+    fn escapes_and_separators() {
+        let mut vars = Vars::default();
+        vars.set("title", "a/b");
+        assert_eq!(render("{{[[{title}]]}}", &vars), "{[a_b]}");
+        assert_eq!(render("/ x //{title}/ ", &vars), "x/a_b");
+    }
+
+    #[test]
+    fn bad_templates() {
+        for t in ["{title", "{nope}", "title}", "[{title}", "{title}]"] {
+            assert!(Template::parse(t).is_err(), "{}", t);
+        }
+    }
+
+    #[test]
+    fn time_placeholders() {
+        let mut vars = Vars::default();
+        vars.set_time("pubdate", 1582905600).set_time("fav_time", 0);
+        assert_eq!(render("{pubdate}", &vars), "2020-02-29");
+        assert_eq!(render("{pubdate:%Y%m}[ {fav_time}]", &vars), "202002");
+    }
+
+    #[test]
+    fn format_time_edges() {
+        assert_eq!(
+            format_time(1582905600, "%Y-%m-%d %H:%M:%S"),
+            "2020-02-29 00:00:00"
+        );
+        assert_eq!(format_time(951753600, "%Y-%m-%d"), "2000-02-29");
+        assert_eq!(format_time(-2203920000, "%Y-%m-%d"), "1900-03-01");
+        assert_eq!(
+            format_time(1614527999, "%Y-%m-%d %H:%M:%S"),
+            "2021-02-28 23:59:59"
+        );
+        assert_eq!(format_time(-1, "%Y-%m-%d %H"), "1970-01-01 07");
+        assert_eq!(format_time(0, "100%% %"), "100% %");
+    }
+
+    #[test]
+    fn days_from_civil_inverts_format_time() {
+        for days in -800_000..800_000 {
+            if days % 997 != 0 {
+                continue;
+            }
+            let t = days * 86400 - 8 * 3600;
+            let ymd = format_time(t, "%Y %m %d");
+            let n: Vec<i64> = ymd.split(' ').map(|n| n.parse().unwrap()).collect();
+            assert_eq!(days_from_civil(n[0], n[1], n[2]), days, "{}", ymd);
+        }
+    }
+
+    #[test]
+    fn parse_dates() {
+        assert_eq!(parse_date("2020"), Some((1577808000, 1609430399)));
+        assert_eq!(parse_date("2020-12"), Some((1606752000, 1609430399)));
+        assert_eq!(parse_date(" 2020-2-29 "), Some((1582905600, 1582991999)));
+        assert_eq!(parse_date("2021-02-29"), None);
+        assert_eq!(parse_date("1900-02-29"), None);
+        assert_eq!(parse_date("2020-13"), None);
+        assert_eq!(parse_date("2020-"), None);
+        assert_eq!(parse_date("9999999999999999"), None);
+        assert_eq!(parse_date("0-1-1"), None);
+        assert_eq!(
+            parse_date("9999-12-31"),
+            Some((253402271999 - 86400 + 1, 253402271999))
+        );
+        assert_eq!(parse_date(""), None);
+    }
+}
@@ -0,0 +1,242 @@
+use serde::{Deserialize, Serialize};
+
+/// 标题和标签中的简繁转换
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Script {
+    #[default]
+    Keep,
+    Simplified,
+    Traditional,
+}
+
+impl Script {
+    pub const ALL: [Script; 3] = [Script::Keep, Script::Simplified, Script::Traditional];
+}
+
+impl std::fmt::Display for Script {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Script::Keep => "keep",
+            Script::Simplified => "hans",
+            Script::Traditional => "hant",
+        })
+    }
+}
+
+impl std::str::FromStr for Script {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Script, String> {
+        match s.to_lowercase().as_str() {
+            "keep" => Ok(Script::Keep),
+            "hans" | "simplified" => Ok(Script::Simplified),
+            "hant" | "traditional" => Ok(Script::Traditional),
+            _ => Err(format!("未知的简繁转换：{}", s)),
+        }
+    }
+}
+
+/// 编译时是否启用了`transliterate` feature。没有启用时不能转换简繁，
+/// 转成ASCII时汉字会被直接去掉
+pub const AVAILABLE: bool = cfg!(feature = "transliterate");
+
+#[cfg(feature = "transliterate")]
+pub fn convert_script(s: &str, script: Script) -> String {
+    match script {
+        Script::Keep => s.to_string(),
+        Script::Simplified => zhconv::zhconv(s, zhconv::Variant::ZhHans),
+        Script::Traditional => zhconv::zhconv(s, zhconv::Variant::ZhHant),
+    }
+}
+
+#[cfg(not(feature = "transliterate"))]
+pub fn convert_script(s: &str, _script: Script) -> String {
+    s.to_string()
+}
+
+#[cfg(feature = "transliterate")]
+fn han_to_pinyin(c: char) -> Option<String> {
+    use pinyin::ToPinyin;
+    c.to_pinyin().map(|p| {
+        let mut s = p.plain().to_string();
+        if let Some(f) = s.get_mut(0..1) {
+            f.make_ascii_uppercase();
+        }
+        s
+    })
+}
+
+#[cfg(not(feature = "transliterate"))]
+fn han_to_pinyin(_c: char) -> Option<String> {
+    None
+}
+
+/// 转成只有ASCII字符的文字：汉字转拼音，假名转罗马字，全角符号转半角，
+/// 其它非ASCII字符去掉
+pub fn to_ascii(s: &str) -> String {
+    let chars: Vec<char> = s.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        if c.is_ascii() {
+            out.push(c);
+        } else if let Some(p) = han_to_pinyin(c) {
+            out.push(' ');
+            out.push_str(&p);
+            out.push(' ');
+        } else if let Some(k) = hiragana(c) {
+            let (romaji, used) = kana_to_romaji(k, &chars[i..]);
+            i += used;
+            out.push_str(&romaji);
+        } else if let Some(a) = punctuation(c) {
+            out.push(a);
+        } else if c.is_whitespace() {
+            out.push(' ');
+        }
+    }
+    // 拼音前后加的空格可能重复
+    out.split(' ')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn punctuation(c: char) -> Option<char> {
+    match c {
+        // 全角ASCII
+        '\u{ff01}'..='\u{ff5e}' => std::char::from_u32(c as u32 - 0xfee0),
+        '\u{3000}' => Some(' '),
+        '、' => Some(','),
+        '。' | '・' => Some('.'),
+        '【' | '「' | '『' | '〔' | '《' | '〈' => Some('['),
+        '】' | '」' | '』' | '〕' | '》' | '〉' => Some(']'),
+        '〜' => Some('~'),
+        '—' | '–' => Some('-'),
+        '‘' | '’' => Some('\''),
+        '“' | '”' => Some('"'),
+        '…' => Some('.'),
+        _ => None,
+    }
+}
+
+/// 片假名转成对应的平假名，长音符号原样保留
+fn hiragana(c: char) -> Option<char> {
+    match c {
+        'ぁ'..='ゖ' | 'ー' => Some(c),
+        'ァ'..='ヶ' => std::char::from_u32(c as u32 - 0x60),
+        _ => None,
+    }
+}
+
+#[rustfmt::skip]
+const KANA: &[(&str, &str)] = &[
+    ("あ", "a"), ("い", "i"), ("う", "u"), ("え", "e"), ("お", "o"),
+    ("か", "ka"), ("き", "ki"), ("く", "ku"), ("け", "ke"), ("こ", "ko"),
+    ("さ", "sa"), ("し", "shi"), ("す", "su"), ("せ", "se"), ("そ", "so"),
+    ("た", "ta"), ("ち", "chi"), ("つ", "tsu"), ("て", "te"), ("と", "to"),
+    ("な", "na"), ("に", "ni"), ("ぬ", "nu"), ("ね", "ne"), ("の", "no"),
+    ("は", "ha"), ("ひ", "hi"), ("ふ", "fu"), ("へ", "he"), ("ほ", "ho"),
+    ("ま", "ma"), ("み", "mi"), ("む", "mu"), ("め", "me"), ("も", "mo"),
+    ("や", "ya"), ("ゆ", "yu"), ("よ", "yo"),
+    ("ら", "ra"), ("り", "ri"), ("る", "ru"), ("れ", "re"), ("ろ", "ro"),
+    ("わ", "wa"), ("ゐ", "i"), ("ゑ", "e"), ("を", "o"), ("ん", "n"),
+    ("が", "ga"), ("ぎ", "gi"), ("ぐ", "gu"), ("げ", "ge"), ("ご", "go"),
+    ("ざ", "za"), ("じ", "ji"), ("ず", "zu"), ("ぜ", "ze"), ("ぞ", "zo"),
+    ("だ", "da"), ("ぢ", "ji"), ("づ", "zu"), ("で", "de"), ("ど", "do"),
+    ("ば", "ba"), ("び", "bi"), ("ぶ", "bu"), ("べ", "be"), ("ぼ", "bo"),
+    ("ぱ", "pa"), ("ぴ", "pi"), ("ぷ", "pu"), ("ぺ", "pe"), ("ぽ", "po"),
+    ("ぁ", "a"), ("ぃ", "i"), ("ぅ", "u"), ("ぇ", "e"), ("ぉ", "o"),
+    ("ゃ", "ya"), ("ゅ", "yu"), ("ょ", "yo"), ("ゎ", "wa"), ("ゔ", "vu"),
+];
+
+fn kana(c: char) -> Option<&'static str> {
+    let mut buf = [0; 4];
+    let c: &str = c.encode_utf8(&mut buf);
+    KANA.iter().find(|(k, _)| *k == c).map(|(_, r)| *r)
+}
+
+/// 转换从`c`开始的假名，返回罗马字和额外用掉的字符数。
+/// 处理拗音（きゃ→kya）、促音（っか→kka）和长音（ー重复前一个元音）
+fn kana_to_romaji(c: char, rest: &[char]) -> (String, usize) {
+    match c {
+        'ー' => return (String::new(), 0),
+        'っ' => {
+            let next = rest.first().and_then(|n| hiragana(*n));
+            return match next.map(|n| kana_to_romaji(n, &rest[1..])) {
+                Some((r, used)) if !r.is_empty() => {
+                    let first = if r.starts_with("ch") {
+                        't'
+                    } else {
+                        r.as_bytes()[0] as char
+                    };
+                    (format!("{}{}", first, r), used + 1)
+                }
+                _ => (String::new(), 0),
+            };
+        }
+        _ => {}
+    }
+    let base = match kana(c) {
+        Some(b) => b,
+        None => return (String::new(), 0),
+    };
+    let mut used = 0;
+    let mut r = base.to_string();
+    // 小写的ゃゅょ和前面的い段假名合成拗音
+    if let Some(small) = rest.first().and_then(|n| hiragana(*n)) {
+        let y = match small {
+            'ゃ' => Some('a'),
+            'ゅ' => Some('u'),
+            'ょ' => Some('o'),
+            _ => None,
+        };
+        if let (Some(y), true) = (y, base.ends_with('i') && base.len() > 1) {
+            let stem = &base[..base.len() - 1];
+            r = match stem {
+                "sh" | "ch" | "j" => format!("{}{}", stem, y),
+                _ => format!("{}y{}", stem, y),
+            };
+            used = 1;
+        }
+    }
+    // 长音符号重复最后一个元音
+    if rest.get(used).and_then(|n| hiragana(*n)) == Some('ー') {
+        if let Some(v) = r.chars().last() {
+            r.push(v);
+        }
+        used += 1;
+    }
+    (r, used)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kana_to_romaji() {
+        assert_eq!(to_ascii("きょうは"), "kyouha");
+        assert_eq!(to_ascii("ちょっと"), "chotto");
+        assert_eq!(to_ascii("マッチ"), "matchi");
+        assert_eq!(to_ascii("ラーメン"), "raamen");
+        assert_eq!(to_ascii("ジャズ"), "jazu");
+        // 结尾的促音和单独的长音没有对应的罗马字
+        assert_eq!(to_ascii("あっ"), "a");
+        assert_eq!(to_ascii("ー"), "");
+    }
+
+    #[test]
+    fn punctuation_and_spaces() {
+        assert_eq!(to_ascii("ｈｅｌｌｏ　【ＭＶ】"), "hello [MV]");
+        assert_eq!(to_ascii("a 、 b…"), "a , b.");
+        assert_eq!(to_ascii("한국어"), "");
+    }
+
+    #[cfg(feature = "transliterate")]
+    #[test]
+    fn han_to_pinyin() {
+        assert_eq!(to_ascii("你好world"), "Ni Hao world");
+        assert_eq!(convert_script("後來", Script::Simplified), "后来");
+    }
+}
@@ -0,0 +1,248 @@
+use crate::bapi::Credential;
+use crate::dirs::{config_dir, write_private};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// 加密保存登录凭据的文件名，在配置目录中
+pub const VAULT_NAME: &str = "credentials.bin";
+
+/// 编译时是否启用了`secure-store` feature。没有启用时Cookie不会写入文件，
+/// 每次启动都要重新登录
+pub const AVAILABLE: bool = cfg!(feature = "secure-store");
+
+/// 文件开头的标记和格式版本，之后是盐、nonce和密文
+const MAGIC: &[u8] = b"BMDV\x01";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+/// 用口令加密的凭据库，账号名→凭据。
+///
+/// 口令经Argon2id派生出密钥，内容用ChaCha20-Poly1305加密。解锁一次后
+/// 密钥留在内存中，同一次运行中保存时不用再输入口令
+pub struct Vault {
+    path: PathBuf,
+    salt: [u8; SALT_LEN],
+    key: [u8; KEY_LEN],
+    credentials: HashMap<String, Credential>,
+}
+
+impl std::fmt::Debug for Vault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Vault")
+            .field("path", &self.path)
+            .field("accounts", &self.credentials.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Vault {
+    pub fn path() -> Option<PathBuf> {
+        config_dir().map(|d| d.join(VAULT_NAME))
+    }
+
+    /// 是否已经有凭据库，没有时`unlock`的口令会成为新的口令
+    pub fn exists() -> bool {
+        Vault::path().is_some_and(|p| p.exists())
+    }
+
+    /// 用口令解锁。文件不存在时新建一个空的，第一次`save`时写入
+    pub fn unlock(passphrase: &str) -> Result<Vault, String> {
+        let path = Vault::path().ok_or_else(|| String::from("找不到配置目录"))?;
+        Vault::unlock_at(path, passphrase)
+    }
+
+    fn unlock_at(path: PathBuf, passphrase: &str) -> Result<Vault, String> {
+        if passphrase.is_empty() {
+            return Err(String::from("口令不能为空"));
+        }
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let mut salt = [0; SALT_LEN];
+                crypto::random(&mut salt)?;
+                return Ok(Vault {
+                    key: crypto::derive_key(passphrase, &salt)?,
+                    path,
+                    salt,
+                    credentials: HashMap::new(),
+                });
+            }
+            Err(e) => return Err(format!("凭据文件读取失败：{}", e)),
+        };
+        let body = data
+            .strip_prefix(MAGIC)
+            .filter(|b| b.len() > SALT_LEN + NONCE_LEN)
+            .ok_or_else(|| String::from("凭据文件格式不对"))?;
+        let mut salt = [0; SALT_LEN];
+        salt.copy_from_slice(&body[..SALT_LEN]);
+        let key = crypto::derive_key(passphrase, &salt)?;
+        let plain = crypto::open(&key, &body[SALT_LEN..])
+            .map_err(|_| String::from("口令错误或凭据文件已损坏"))?;
+        let credentials =
+            serde_json::from_slice(&plain).map_err(|e| format!("凭据文件格式不对：{}", e))?;
+        Ok(Vault {
+            path,
+            salt,
+            key,
+            credentials,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Credential> {
+        self.credentials.get(name)
+    }
+
+    pub fn insert(&mut self, name: &str, credential: Credential) {
+        self.credentials.insert(name.to_string(), credential);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.credentials.remove(name);
+    }
+
+    /// 每次保存用新的nonce重新加密整个文件
+    pub fn save(&self) -> Result<(), String> {
+        let plain = serde_json::to_vec(&self.credentials).map_err(|e| e.to_string())?;
+        let sealed = crypto::seal(&self.key, &plain)?;
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&self.salt);
+        data.extend_from_slice(&sealed);
+        let write = || -> std::io::Result<()> {
+            if let Some(dir) = self.path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            write_private(&self.path, &data)
+        };
+        write().map_err(|e| format!("凭据文件写入失败：{}", e))
+    }
+}
+
+#[cfg(feature = "secure-store")]
+mod crypto {
+    use super::{KEY_LEN, NONCE_LEN};
+    use chacha20poly1305::aead::rand_core::RngCore;
+    use chacha20poly1305::aead::{Aead, KeyInit, OsRng};
+    use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+    pub fn random(buf: &mut [u8]) -> Result<(), String> {
+        OsRng
+            .try_fill_bytes(buf)
+            .map_err(|e| format!("无法取得随机数：{}", e))
+    }
+
+    /// Argon2id，参数用argon2的默认值
+    pub fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; KEY_LEN], String> {
+        let mut key = [0; KEY_LEN];
+        argon2::Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| format!("密钥派生失败：{}", e))?;
+        Ok(key)
+    }
+
+    /// 返回nonce和密文
+    pub fn seal(key: &[u8; KEY_LEN], plain: &[u8]) -> Result<Vec<u8>, String> {
+        let mut nonce = [0; NONCE_LEN];
+        random(&mut nonce)?;
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+        let sealed = cipher
+            .encrypt(Nonce::from_slice(&nonce), plain)
+            .map_err(|_| String::from("加密失败"))?;
+        Ok([&nonce[..], &sealed].concat())
+    }
+
+    pub fn open(key: &[u8; KEY_LEN], data: &[u8]) -> Result<Vec<u8>, String> {
+        let (nonce, sealed) = data.split_at(NONCE_LEN);
+        ChaCha20Poly1305::new(Key::from_slice(key))
+            .decrypt(Nonce::from_slice(nonce), sealed)
+            .map_err(|_| String::from("解密失败"))
+    }
+}
+
+#[cfg(not(feature = "secure-store"))]
+mod crypto {
+    use super::KEY_LEN;
+
+    const UNAVAILABLE: &str = "没有启用secure-store feature，不能加密保存Cookie";
+
+    pub fn random(_buf: &mut [u8]) -> Result<(), String> {
+        Err(String::from(UNAVAILABLE))
+    }
+
+    pub fn derive_key(_passphrase: &str, _salt: &[u8]) -> Result<[u8; KEY_LEN], String> {
+        Err(String::from(UNAVAILABLE))
+    }
+
+    pub fn seal(_key: &[u8; KEY_LEN], _plain: &[u8]) -> Result<Vec<u8>, String> {
+        Err(String::from(UNAVAILABLE))
+    }
+
+    pub fn open(_key: &[u8; KEY_LEN], _data: &[u8]) -> Result<Vec<u8>, String> {
+        Err(String::from(UNAVAILABLE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Vault::unlock_at(dir.path().join("vault.bin"), "").is_err());
+    }
+
+    #[cfg(feature = "secure-store")]
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vault.bin");
+        let mut vault = Vault::unlock_at(path.clone(), "口令").unwrap();
+        vault.insert("a", Credential::from_sessdata("s1"));
+        vault.save().unwrap();
+        // 每次保存用新的nonce
+        let first = std::fs::read(&path).unwrap();
+        vault.save().unwrap();
+        assert_ne!(first, std::fs::read(&path).unwrap());
+
+        let vault = Vault::unlock_at(path.clone(), "口令").unwrap();
+        assert_eq!(vault.get("a").unwrap().sessdata, "s1");
+        assert!(vault.get("b").is_none());
+    }
+
+    #[cfg(feature = "secure-store")]
+    #[test]
+    fn wrong_passphrase_and_corrupt_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vault.bin");
+        let mut vault = Vault::unlock_at(path.clone(), "right").unwrap();
+        vault.insert("a", Credential::from_sessdata("s1"));
+        vault.save().unwrap();
+        assert_eq!(
+            Vault::unlock_at(path.clone(), "wrong").unwrap_err(),
+            "口令错误或凭据文件已损坏"
+        );
+
+        let mut data = std::fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(
+            Vault::unlock_at(path.clone(), "right").unwrap_err(),
+            "口令错误或凭据文件已损坏"
+        );
+
+        std::fs::write(&path, b"BMDV\x01short").unwrap();
+        assert_eq!(
+            Vault::unlock_at(path.clone(), "right").unwrap_err(),
+            "凭据文件格式不对"
+        );
+    }
+
+    #[cfg(not(feature = "secure-store"))]
+    #[test]
+    fn unavailable_without_feature() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Vault::unlock_at(dir.path().join("vault.bin"), "口令").is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// 监视模式在输出目录中保存状态的文件名
+pub const WATCH_STATE_NAME: &str = ".bili_music_download_watch.json";
+
+/// 每个收藏夹已经处理到的最后一次收藏时间，重启后从这里继续
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WatchState {
+    #[serde(skip)]
+    dir: PathBuf,
+    last_fav_time: HashMap<String, i64>,
+}
+
+impl WatchState {
+    pub fn load(dir: &str) -> WatchState {
+        let dir = PathBuf::from(dir);
+        let mut state: WatchState = std::fs::read(dir.join(WATCH_STATE_NAME))
+            .ok()
+            .and_then(|s| serde_json::from_slice(&s).ok())
+            .unwrap_or_default();
+        state.dir = dir;
+        state
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let tmp = self.dir.join(format!("{}.tmp", WATCH_STATE_NAME));
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, self.dir.join(WATCH_STATE_NAME))
+    }
+
+    /// 没有记录时为0，即下载整个收藏夹
+    pub fn last(&self, fid: &str) -> i64 {
+        self.last_fav_time.get(fid).cloned().unwrap_or_default()
+    }
+
+    pub fn set_last(&mut self, fid: &str, fav_time: i64) {
+        self.last_fav_time.insert(fid.to_string(), fav_time);
+    }
+}
+
+/// 在`interval`秒上加上不超过`jitter`秒的随机延迟，避免总在同一时刻请求
+pub fn next_delay(interval: u64, jitter: u64) -> Duration {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or_default();
+    Duration::from_secs(interval.saturating_add(nanos % jitter.saturating_add(1)))
+}
+
+/// 等待Ctrl+C或SIGTERM。下载中途退出时，未写完的文件不会记入清单
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut term) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = term.recv() => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+            return;
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}